@@ -0,0 +1,597 @@
+//! Declarative activity definitions
+//!
+//! An activity can be described in RON instead of building its widgets by hand:
+//! ```ron
+//! (
+//!     name: "music",
+//!     css_classes: ["music"],
+//!     properties: { "title": String("nothing playing"), "progress": Float(0.0) },
+//!     initial_mode: Compact,
+//!     minimal: (kind: Image(icon_name: Some("audio-x-generic"))),
+//!     compact: Some((
+//!         kind: ScrollingLabel(text: "", max_width: Some(120)),
+//!         css_classes: ["title"],
+//!         halign: Some(Center),
+//!         bindings: [(property: "title", target: "text")],
+//!     )),
+//!     expanded: Some((
+//!         kind: Box(orientation: Vertical, spacing: 4, children: [
+//!             (kind: Label(text: ""), bindings: [(property: "title", target: "label")]),
+//!             (kind: Label(text: ""), bindings: [(property: "progress", target: "label", format: Some("{}%"))]),
+//!         ]),
+//!         width: Some(300),
+//!         height: Some(80),
+//!     )),
+//! )
+//! ```
+//!
+//! The bindings update a widget property every time the dynamic property changes,
+//! the value is converted to the type of the widget property when possible.
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+use abi::{glib, gtk, log};
+use anyhow::{anyhow, bail, Context, Result};
+use gtk::prelude::*;
+use notify::RecommendedWatcher;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    base_module::BaseModule,
+    dynamic_activity::DynamicActivity,
+    dynamic_property::ValidDynType,
+    graphics::{
+        activity_widget::{boxed_activity_mode::ActivityMode, ActivityWidget},
+        widgets::{rolling_char::RollingChar, scrolling_label::ScrollingLabel},
+    },
+    module_config::{watch_path, ConfigError},
+};
+
+/// The description of a `DynamicActivity` and of its `ActivityWidget`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActivityDefinition {
+    /// The base name of the activity
+    pub name: String,
+    #[serde(default)]
+    pub window_name: Option<String>,
+    /// The css classes of the `ActivityWidget`
+    #[serde(default)]
+    pub css_classes: Vec<String>,
+    /// The dynamic properties of the activity with their initial value
+    #[serde(default)]
+    pub properties: HashMap<String, PropertyValue>,
+    #[serde(default = "default_mode")]
+    pub initial_mode: ActivityMode,
+    pub minimal: WidgetDefinition,
+    #[serde(default)]
+    pub compact: Option<WidgetDefinition>,
+    #[serde(default)]
+    pub expanded: Option<WidgetDefinition>,
+    #[serde(default)]
+    pub overlay: Option<WidgetDefinition>,
+}
+
+fn default_mode() -> ActivityMode {
+    ActivityMode::Minimal
+}
+
+/// The initial value of a dynamic property, this also decides its type
+///
+/// The module has to use the same type when setting the property
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PropertyValue {
+    String(String),
+    Char(char),
+    Bool(bool),
+    Int(i32),
+    Float(f64),
+}
+
+/// A widget inside one of the modes of the activity
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WidgetDefinition {
+    pub kind: WidgetKind,
+    /// The widget name, can be used in the css
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub css_classes: Vec<String>,
+    #[serde(default)]
+    pub halign: Option<Align>,
+    #[serde(default)]
+    pub valign: Option<Align>,
+    #[serde(default)]
+    pub hexpand: Option<bool>,
+    #[serde(default)]
+    pub vexpand: Option<bool>,
+    /// The requested width, used by the `ActivityWidget` during the mode changes
+    #[serde(default)]
+    pub width: Option<i32>,
+    /// The requested height, used by the `ActivityWidget` during the mode changes
+    #[serde(default)]
+    pub height: Option<i32>,
+    /// Other properties of the widget to set, by GObject property name
+    #[serde(default)]
+    pub set: HashMap<String, PropertyValue>,
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum WidgetKind {
+    Box {
+        #[serde(default)]
+        orientation: Orientation,
+        #[serde(default)]
+        spacing: i32,
+        #[serde(default)]
+        children: Vec<WidgetDefinition>,
+    },
+    Label {
+        #[serde(default)]
+        text: String,
+    },
+    ScrollingLabel {
+        #[serde(default)]
+        text: String,
+        #[serde(default)]
+        max_width: Option<i32>,
+    },
+    RollingChar {
+        #[serde(default)]
+        char: Option<char>,
+    },
+    Image {
+        #[serde(default)]
+        icon_name: Option<String>,
+        #[serde(default)]
+        file: Option<String>,
+        #[serde(default)]
+        pixel_size: Option<i32>,
+    },
+}
+
+/// Updates a widget property when a dynamic property changes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    /// The name of the dynamic property
+    pub property: String,
+    /// The name of the widget property
+    pub target: String,
+    /// Format the value as a string, `{}` is replaced with the value
+    ///
+    /// example: `"{}%"`
+    #[serde(default)]
+    pub format: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+impl From<Orientation> for gtk::Orientation {
+    fn from(value: Orientation) -> Self {
+        match value {
+            Orientation::Horizontal => gtk::Orientation::Horizontal,
+            Orientation::Vertical => gtk::Orientation::Vertical,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Align {
+    Fill,
+    Start,
+    End,
+    Center,
+    Baseline,
+}
+
+impl From<Align> for gtk::Align {
+    fn from(value: Align) -> Self {
+        match value {
+            Align::Fill => gtk::Align::Fill,
+            Align::Start => gtk::Align::Start,
+            Align::End => gtk::Align::End,
+            Align::Center => gtk::Align::Center,
+            Align::Baseline => gtk::Align::Baseline,
+        }
+    }
+}
+
+impl ActivityDefinition {
+    /// Parse a definition from a RON string
+    pub fn from_ron(source: &str) -> Result<Self, ConfigError> {
+        Ok(ron::from_str(source)?)
+    }
+
+    /// Read and parse a definition from a RON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read activity definition {:?}", path))?;
+        Self::from_ron(&content).with_context(|| format!("invalid activity definition {:?}", path))
+    }
+
+    /// Build a new `DynamicActivity` with its dynamic properties and its `ActivityWidget`
+    ///
+    /// returns `Err` if a binding refers to a property that isn't defined
+    pub fn build<T>(&self, base_module: &BaseModule<T>) -> Result<DynamicActivity> {
+        let mut activity = DynamicActivity::new_with_metadata(
+            base_module.prop_send(),
+            base_module.name(),
+            &self.name,
+            self.window_name.as_deref(),
+            Vec::new(),
+        );
+        self.rebuild(&mut activity)?;
+        Ok(activity)
+    }
+
+    /// Replace the `ActivityWidget` and the bindings of an activity built from a definition
+    ///
+    /// The dynamic properties already in the activity keep their value, the new ones are added.
+    ///
+    /// Only the subscribers of the old bindings are removed, the ones added by the module are kept.
+    ///
+    /// If the activity is registered it has to be unregistered before calling this, view [`set_activity_widget`](DynamicActivity::set_activity_widget)
+    ///
+    /// returns `Err` and leaves the activity unchanged if a widget can't be built or if a binding refers to a property that isn't defined
+    pub fn rebuild(&self, activity: &mut DynamicActivity) -> Result<()> {
+        let mut builder = ActivityWidget::builder()
+            .name(&activity.get_activity_widget().name())
+            .css_class(&self.name)
+            .css_classes(&self.css_classes)
+            .mode(self.initial_mode);
+        let mut bindings = Vec::new();
+        let modes = [
+            (ActivityMode::Minimal, Some(&self.minimal)),
+            (ActivityMode::Compact, self.compact.as_ref()),
+            (ActivityMode::Expanded, self.expanded.as_ref()),
+            (ActivityMode::Overlay, self.overlay.as_ref()),
+        ];
+        for (mode, definition) in modes {
+            let Some(definition) = definition else {
+                continue;
+            };
+            let child = definition
+                .build(&mut bindings)
+                .with_context(|| format!("failed to build the {mode} widget"))?;
+            builder = builder.mode_widget(mode, &child);
+        }
+        let widget = builder.build()?;
+
+        // check every binding before changing the activity, so it stays the same on errors
+        for (_, binding) in bindings.iter() {
+            if !self.properties.contains_key(&binding.property)
+                && activity.get_property_any(&binding.property).is_err()
+            {
+                bail!(
+                    "invalid binding to {}: property {} doesn't exist",
+                    binding.target,
+                    binding.property
+                );
+            }
+        }
+
+        for (name, value) in self.properties.iter() {
+            if activity.get_property_any(name).is_ok() {
+                continue;
+            }
+            match value.clone() {
+                PropertyValue::String(value) => activity.add_dynamic_property(name, value)?,
+                PropertyValue::Char(value) => activity.add_dynamic_property(name, value)?,
+                PropertyValue::Bool(value) => activity.add_dynamic_property(name, value)?,
+                PropertyValue::Int(value) => activity.add_dynamic_property(name, value)?,
+                PropertyValue::Float(value) => activity.add_dynamic_property(name, value)?,
+            }
+        }
+        activity.clear_binding_subscribers();
+        for (target, binding) in bindings {
+            let property = activity.get_property_any(&binding.property)?;
+            let name = binding.property.clone();
+            let update = move |value: &dyn ValidDynType| {
+                if let Err(err) = apply_binding(&target, &binding, value) {
+                    log::error!("{err:#}");
+                }
+            };
+            // show the current value before the first update
+            update(property.blocking_lock().get());
+            activity.subscribe_binding(&name, update)?;
+        }
+        activity.set_activity_widget(widget);
+        Ok(())
+    }
+
+    /// Build an activity from a file and rebuild it when the file changes
+    ///
+    /// If the activity is registered when the file changes, it's registered again with the new widget
+    /// and the same mode, if the new definition has a widget for it.
+    /// The name and the window can't change while watching.
+    ///
+    /// The file stops being watched when the returned `WatchedActivity` is dropped
+    pub fn watch_file<T: 'static>(
+        path: impl AsRef<Path>,
+        base_module: &BaseModule<T>,
+    ) -> Result<WatchedActivity> {
+        let path = path.as_ref().to_path_buf();
+        let definition = Self::from_file(&path)?;
+        let activity = Rc::new(Mutex::new(definition.build(base_module)?));
+        let definition = Rc::new(RefCell::new(definition));
+
+        let base_module = base_module.clone();
+        let watched_activity = activity.clone();
+        let watched_definition = definition.clone();
+        let watched = path.clone();
+        let watcher = watch_path(&path, move || {
+            let res = Self::from_file(&watched).and_then(|new_definition| {
+                reload(
+                    &base_module,
+                    &watched_activity,
+                    &watched_definition,
+                    new_definition,
+                )
+            });
+            match res {
+                Ok(()) => {
+                    log::debug!(
+                        "{}: rebuilt activity from {:?}",
+                        base_module.name(),
+                        watched
+                    );
+                }
+                Err(err) => {
+                    log::error!("{} {:?}: {:#}", base_module.name(), watched, err);
+                }
+            }
+        })?;
+        Ok(WatchedActivity {
+            activity,
+            definition,
+            _watcher: watcher,
+        })
+    }
+}
+
+/// An activity built from a definition file that is rebuilt when the file changes
+///
+/// view [`ActivityDefinition::watch_file`]
+pub struct WatchedActivity {
+    activity: Rc<Mutex<DynamicActivity>>,
+    definition: Rc<RefCell<ActivityDefinition>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl WatchedActivity {
+    /// Get the activity, it stays the same instance after a rebuild
+    /// so it can be registered with `register_activity_rc`
+    pub fn activity(&self) -> Rc<Mutex<DynamicActivity>> {
+        self.activity.clone()
+    }
+
+    /// Get the last definition that was loaded successfully
+    pub fn definition(&self) -> ActivityDefinition {
+        self.definition.borrow().clone()
+    }
+}
+
+fn reload<T>(
+    base_module: &BaseModule<T>,
+    activity: &Rc<Mutex<DynamicActivity>>,
+    definition: &Rc<RefCell<ActivityDefinition>>,
+    new_definition: ActivityDefinition,
+) -> Result<()> {
+    {
+        let old = definition.borrow();
+        if old.name != new_definition.name || old.window_name != new_definition.window_name {
+            bail!("the name and the window of the activity can't change while watching the file");
+        }
+    }
+    let (id, mode) = {
+        let activity = activity.blocking_lock();
+        (
+            activity.get_identifier(),
+            activity.get_activity_widget().mode(),
+        )
+    };
+    let registered = base_module
+        .registered_activities()
+        .blocking_lock()
+        .contains(&id);
+    if registered {
+        base_module.unregister_by_id(&id);
+    }
+    let res = {
+        let mut activity = activity.blocking_lock();
+        let res = new_definition.rebuild(&mut activity);
+        if res.is_ok() {
+            let widget = activity.get_activity_widget();
+            if widget.get_widget_for_mode(mode).is_some() {
+                widget.set_mode(mode);
+            }
+        }
+        res
+    };
+    if registered {
+        base_module.register_activity_rc(activity.clone())?;
+    }
+    res?;
+    *definition.borrow_mut() = new_definition;
+    Ok(())
+}
+
+impl WidgetDefinition {
+    fn build(&self, bindings: &mut Vec<(gtk::Widget, Binding)>) -> Result<gtk::Widget> {
+        let widget: gtk::Widget = match &self.kind {
+            WidgetKind::Box {
+                orientation,
+                spacing,
+                children,
+            } => {
+                let container = gtk::Box::new((*orientation).into(), *spacing);
+                for child in children {
+                    container.append(&child.build(bindings)?);
+                }
+                container.upcast()
+            }
+            WidgetKind::Label { text } => gtk::Label::new(Some(text)).upcast(),
+            WidgetKind::ScrollingLabel { text, max_width } => {
+                let label = ScrollingLabel::new();
+                label.set_text(text.as_str());
+                if let Some(max_width) = max_width {
+                    label.set_max_width(*max_width);
+                }
+                label.upcast()
+            }
+            WidgetKind::RollingChar { char } => RollingChar::new(*char).upcast(),
+            WidgetKind::Image {
+                icon_name,
+                file,
+                pixel_size,
+            } => {
+                let image = match (icon_name, file) {
+                    (Some(icon_name), _) => gtk::Image::from_icon_name(icon_name),
+                    (None, Some(file)) => gtk::Image::from_file(file),
+                    (None, None) => gtk::Image::new(),
+                };
+                if let Some(pixel_size) = pixel_size {
+                    image.set_pixel_size(*pixel_size);
+                }
+                image.upcast()
+            }
+        };
+
+        if let Some(name) = &self.name {
+            widget.set_widget_name(name);
+        }
+        for class in self.css_classes.iter() {
+            widget.add_css_class(class);
+        }
+        if let Some(halign) = self.halign {
+            widget.set_halign(halign.into());
+        }
+        if let Some(valign) = self.valign {
+            widget.set_valign(valign.into());
+        }
+        if let Some(hexpand) = self.hexpand {
+            widget.set_hexpand(hexpand);
+        }
+        if let Some(vexpand) = self.vexpand {
+            widget.set_vexpand(vexpand);
+        }
+        widget.set_size_request(self.width.unwrap_or(-1), self.height.unwrap_or(-1));
+        for (name, value) in self.set.iter() {
+            let value = match value {
+                PropertyValue::String(value) => value.to_value(),
+                PropertyValue::Char(value) => value.to_value(),
+                PropertyValue::Bool(value) => value.to_value(),
+                PropertyValue::Int(value) => value.to_value(),
+                PropertyValue::Float(value) => value.to_value(),
+            };
+            set_converted(&widget, name, value)?;
+        }
+        for binding in self.bindings.iter() {
+            if widget.find_property(&binding.target).is_none() {
+                bail!(
+                    "{} doesn't have a property named {}",
+                    widget.type_().name(),
+                    binding.target
+                );
+            }
+            bindings.push((widget.clone(), binding.clone()));
+        }
+        Ok(widget)
+    }
+}
+
+fn apply_binding(widget: &gtk::Widget, binding: &Binding, value: &dyn ValidDynType) -> Result<()> {
+    let value = match &binding.format {
+        Some(format) => {
+            let value = dyn_to_string(value)
+                .ok_or_else(|| anyhow!("property {} can't be formatted", binding.property))?;
+            format.replace("{}", &value).to_value()
+        }
+        None => dyn_to_value(value)
+            .ok_or_else(|| anyhow!("property {} has an unsupported type", binding.property))?,
+    };
+    set_converted(widget, &binding.target, value)
+        .with_context(|| format!("failed to bind {}", binding.property))
+}
+
+/// Set a widget property, converting the value to the property type if needed
+fn set_converted(widget: &gtk::Widget, name: &str, value: glib::Value) -> Result<()> {
+    let pspec = widget.find_property(name).ok_or_else(|| {
+        anyhow!(
+            "{} doesn't have a property named {}",
+            widget.type_().name(),
+            name
+        )
+    })?;
+    let value = if value.type_() == pspec.value_type() {
+        value
+    } else {
+        value.transform_with_type(pspec.value_type()).map_err(|_| {
+            anyhow!(
+                "can't convert {} to {} for property {}",
+                value.type_().name(),
+                pspec.value_type().name(),
+                name
+            )
+        })?
+    };
+    widget.set_property_from_value(name, &value);
+    Ok(())
+}
+
+fn dyn_to_value(value: &dyn ValidDynType) -> Option<glib::Value> {
+    let value = value.as_any();
+    if let Some(value) = value.downcast_ref::<String>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<&'static str>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<char>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<bool>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<i32>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<i64>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<u32>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<u64>() {
+        Some(value.to_value())
+    } else if let Some(value) = value.downcast_ref::<f32>() {
+        Some(value.to_value())
+    } else {
+        value.downcast_ref::<f64>().map(|value| value.to_value())
+    }
+}
+
+fn dyn_to_string(value: &dyn ValidDynType) -> Option<String> {
+    let value = value.as_any();
+    if let Some(value) = value.downcast_ref::<String>() {
+        Some(value.clone())
+    } else if let Some(value) = value.downcast_ref::<&'static str>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<char>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<bool>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<i32>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<i64>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<u32>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<u64>() {
+        Some(value.to_string())
+    } else if let Some(value) = value.downcast_ref::<f32>() {
+        Some(value.to_string())
+    } else {
+        value.downcast_ref::<f64>().map(|value| value.to_string())
+    }
+}
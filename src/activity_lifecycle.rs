@@ -0,0 +1,110 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use abi::{glib, gtk, module::ActivityIdentifier};
+use glib::SignalHandlerId;
+use gtk::prelude::*;
+
+use crate::graphics::activity_widget::ActivityWidget;
+
+/// The lifecycle events of an activity registered with a `BaseModule`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ActivityLifecycleEvent {
+    /// The activity is about to be sent to the app
+    BeforeRegister,
+    /// The activity was sent to the app and added to the activity map
+    Registered,
+    /// The activity is about to be removed from the app
+    BeforeUnregister,
+    /// The activity was removed from the app and from the activity map
+    Unregistered,
+    /// The app is showing the activity (the widget is mapped and it doesn't have the `hidden` css class)
+    Visible,
+    /// The app stopped showing the activity
+    Hidden,
+}
+
+/// A callback for the lifecycle events of the activities of a module
+pub type LifecycleHook = Rc<dyn Fn(&ActivityIdentifier, ActivityLifecycleEvent)>;
+
+struct VisibilityTracker {
+    widget: ActivityWidget,
+    handlers: Vec<SignalHandlerId>,
+}
+
+/// Keeps the lifecycle hooks of a module and tracks the visibility of its activities
+#[derive(Default)]
+pub(crate) struct LifecycleHooks {
+    hooks: RefCell<Vec<LifecycleHook>>,
+    visibility: RefCell<HashMap<ActivityIdentifier, VisibilityTracker>>,
+}
+
+impl LifecycleHooks {
+    pub(crate) fn connect(&self, hook: LifecycleHook) {
+        self.hooks.borrow_mut().push(hook);
+    }
+
+    pub(crate) fn emit(&self, activity_id: &ActivityIdentifier, event: ActivityLifecycleEvent) {
+        // clone the list so that hooks can connect other hooks
+        let hooks = self.hooks.borrow().clone();
+        for hook in hooks {
+            hook(activity_id, event);
+        }
+    }
+
+    /// Emit `Visible` and `Hidden` when the app shows or hides the widget
+    pub(crate) fn track_visibility(
+        self: &Rc<Self>,
+        activity_id: &ActivityIdentifier,
+        widget: &ActivityWidget,
+    ) {
+        self.untrack_visibility(activity_id);
+        let visible = Rc::new(Cell::new(false));
+        let update: Rc<dyn Fn(&ActivityWidget)> = {
+            let hooks = Rc::downgrade(self);
+            let activity_id = activity_id.clone();
+            Rc::new(move |widget: &ActivityWidget| {
+                let now_visible = widget.is_mapped() && !widget.has_css_class("hidden");
+                if visible.replace(now_visible) == now_visible {
+                    return;
+                }
+                if let Some(hooks) = hooks.upgrade() {
+                    let event = if now_visible {
+                        ActivityLifecycleEvent::Visible
+                    } else {
+                        ActivityLifecycleEvent::Hidden
+                    };
+                    hooks.emit(&activity_id, event);
+                }
+            })
+        };
+        let mut handlers = Vec::new();
+        let upd = update.clone();
+        handlers.push(widget.connect_map(move |widget| upd(widget)));
+        let upd = update.clone();
+        handlers.push(widget.connect_unmap(move |widget| upd(widget)));
+        let upd = update.clone();
+        handlers
+            .push(widget.connect_notify_local(Some("css-classes"), move |widget, _| upd(widget)));
+        update(widget);
+
+        self.visibility.borrow_mut().insert(
+            activity_id.clone(),
+            VisibilityTracker {
+                widget: widget.clone(),
+                handlers,
+            },
+        );
+    }
+
+    pub(crate) fn untrack_visibility(&self, activity_id: &ActivityIdentifier) {
+        if let Some(tracker) = self.visibility.borrow_mut().remove(activity_id) {
+            for handler in tracker.handlers {
+                tracker.widget.disconnect(handler);
+            }
+        }
+    }
+}
@@ -1,59 +1,201 @@
 use std::{collections::HashMap, rc::Rc, sync::Arc};
 
-use abi::module::ActivityIdentifier;
+use abi::{glib, log, module::ActivityIdentifier};
 use anyhow::{anyhow, bail, Result};
-use tokio::sync::Mutex;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Mutex,
+};
 
 use crate::{dynamic_activity::DynamicActivity, dynamic_property::DynamicPropertyAny};
 
-#[derive(Default)]
+/// A change in the `ActivityMap`
+///
+/// The metadata of the activity (window name and additional metadata) is in the identifier
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActivityMapEvent {
+    Inserted(ActivityIdentifier),
+    Removed(ActivityIdentifier),
+    /// An activity was replaced by another one with the same identifier
+    Replaced(ActivityIdentifier),
+}
+
+impl ActivityMapEvent {
+    pub fn activity_id(&self) -> &ActivityIdentifier {
+        match self {
+            ActivityMapEvent::Inserted(id)
+            | ActivityMapEvent::Removed(id)
+            | ActivityMapEvent::Replaced(id) => id,
+        }
+    }
+}
+
 pub struct ActivityMap {
-    pub(super) map: HashMap<String, Rc<Mutex<DynamicActivity>>>,
+    pub(super) map: HashMap<ActivityIdentifier, Rc<Mutex<DynamicActivity>>>,
+    events: broadcast::Sender<ActivityMapEvent>,
+}
+
+impl Default for ActivityMap {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            map: HashMap::new(),
+            events,
+        }
+    }
 }
 
 /// A helper struct to quickly get dynamic activities and their properties
+///
+/// The activities are identified by the full `ActivityIdentifier` (including the metadata),
+/// so activities with the same base name on different windows don't collide
 impl ActivityMap {
     pub fn new() -> Self {
         Self::default()
     }
     /// Get an activity from the activity name
     ///
+    /// If more than one activity has the same name, any of them is returned,
+    /// use `get_activity_by_id` to get a specific one
+    ///
     /// # Arguments
     /// * `activity_name` - The name of the activity (activity_identifier.activity())
     pub fn get_activity(&self, activity_name: &str) -> Result<Rc<Mutex<DynamicActivity>>> {
         self.map
-            .get(activity_name)
-            .cloned()
+            .iter()
+            .find(|(id, _)| id.activity() == activity_name)
+            .map(|(_, activity)| activity.clone())
             .ok_or_else(|| anyhow!("Activity {} not found", activity_name))
     }
+    /// Get an activity from its full identifier
+    pub fn get_activity_by_id(
+        &self,
+        activity_id: &ActivityIdentifier,
+    ) -> Result<Rc<Mutex<DynamicActivity>>> {
+        self.map
+            .get(activity_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Activity {} not found", activity_id))
+    }
     /// Insert an activity into the map
+    ///
+    /// blocking, use `insert_activity_async` from an async context
     pub fn insert_activity(&mut self, activity: Rc<Mutex<DynamicActivity>>) -> Result<()> {
         let activity_id = activity.blocking_lock().get_identifier();
-        if self.map.contains_key(activity_id.activity()) {
+        self.insert_activity_with_id(activity_id, activity)
+    }
+    /// Insert an activity into the map
+    pub async fn insert_activity_async(
+        &mut self,
+        activity: Rc<Mutex<DynamicActivity>>,
+    ) -> Result<()> {
+        let activity_id = activity.lock().await.get_identifier();
+        self.insert_activity_with_id(activity_id, activity)
+    }
+    pub(crate) fn insert_activity_with_id(
+        &mut self,
+        activity_id: ActivityIdentifier,
+        activity: Rc<Mutex<DynamicActivity>>,
+    ) -> Result<()> {
+        if self.map.contains_key(&activity_id) {
             bail!("activity {} was already registered", activity_id);
         }
-        self.map
-            .insert(activity_id.activity().to_string(), activity);
+        self.map.insert(activity_id.clone(), activity);
+        self.emit(ActivityMapEvent::Inserted(activity_id));
         Ok(())
     }
+    /// Insert an activity into the map, replacing the one with the same identifier if it exists
+    ///
+    /// blocking
+    pub fn replace_activity(&mut self, activity: Rc<Mutex<DynamicActivity>>) {
+        let activity_id = activity.blocking_lock().get_identifier();
+        let event = match self.map.insert(activity_id.clone(), activity) {
+            Some(_) => ActivityMapEvent::Replaced(activity_id),
+            None => ActivityMapEvent::Inserted(activity_id),
+        };
+        self.emit(event);
+    }
     /// Remove an activity from the map
     pub fn remove_activity(&mut self, activity_id: &ActivityIdentifier) -> Result<()> {
-        if !self.map.contains_key(activity_id.activity()) {
+        if self.map.remove(activity_id).is_none() {
             bail!("activity {} wasn't registered", activity_id);
         }
-        self.map.remove(activity_id.activity());
+        self.emit(ActivityMapEvent::Removed(activity_id.clone()));
         Ok(())
     }
+    /// Get a stream of the changes in the map
+    ///
+    /// The receiver is `Send`, so it can be used from the producers
+    pub fn subscribe(&self) -> broadcast::Receiver<ActivityMapEvent> {
+        self.events.subscribe()
+    }
+    /// Call `callback` on the glib main context for every change in the map
+    ///
+    /// The callback is called after the change, when the map is unlocked, so it can lock the map again
+    pub fn connect_event<F>(&self, callback: F)
+    where
+        F: Fn(&ActivityMapEvent) + 'static,
+    {
+        let mut events = self.events.subscribe();
+        glib::MainContext::default().spawn_local(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => callback(&event),
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("activity map listener lagged, skipped {skipped} events");
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+    fn emit(&self, event: ActivityMapEvent) {
+        // returns Err only if there are no subscribers
+        let _ = self.events.send(event);
+    }
+    /// Check if an activity with this identifier is in the map
+    pub fn contains(&self, activity_id: &ActivityIdentifier) -> bool {
+        self.map.contains_key(activity_id)
+    }
     /// Get a list of activity names(activity_identifier.activity())
     pub fn list_activity_names(&self) -> Vec<&str> {
-        self.map.keys().map(|x| x.as_str()).collect()
+        self.map.keys().map(|x| x.activity()).collect()
     }
     /// Get a list of ActivityIdentifiers
     pub fn list_activities(&self) -> Vec<ActivityIdentifier> {
-        self.map
-            .values()
-            .map(|x| x.blocking_lock().get_identifier())
-            .collect()
+        self.map.keys().cloned().collect()
+    }
+    /// Iterate over the identifiers and the activities, without locking them
+    pub fn iter(&self) -> impl Iterator<Item = (&ActivityIdentifier, &Rc<Mutex<DynamicActivity>>)> {
+        self.map.iter()
+    }
+    /// Iterate over the activities whose identifier matches `predicate`
+    pub fn filter<'a, F>(
+        &'a self,
+        predicate: F,
+    ) -> impl Iterator<Item = (&'a ActivityIdentifier, &'a Rc<Mutex<DynamicActivity>>)>
+    where
+        F: Fn(&ActivityIdentifier) -> bool + 'a,
+    {
+        self.map.iter().filter(move |(id, _)| predicate(id))
+    }
+    /// Iterate over the activities on a window
+    ///
+    /// # Arguments
+    /// * `window_name` - The name of the window in the metadata, `None` for the activities on the default window
+    pub fn get_by_window<'a>(
+        &'a self,
+        window_name: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a ActivityIdentifier, &'a Rc<Mutex<DynamicActivity>>)> {
+        self.filter(move |id| id.metadata().window_name().as_deref() == window_name)
+    }
+    /// Iterate over the activities that have `key` set to `value` in the additional metadata
+    pub fn get_by_metadata<'a>(
+        &'a self,
+        key: &'a str,
+        value: &'a str,
+    ) -> impl Iterator<Item = (&'a ActivityIdentifier, &'a Rc<Mutex<DynamicActivity>>)> {
+        self.filter(move |id| id.metadata().additional_metadata(key).as_deref() == Some(value))
     }
     /// Get a property from an activity
     ///
@@ -86,4 +228,19 @@ impl ActivityMap {
             .await
             .get_property_any(property_name)
     }
+    /// Get a property from an activity identified by its full identifier
+    ///
+    /// # Arguments
+    /// * `activity_id` - The identifier of the activity
+    /// * `property_name` - The name of the property
+    pub async fn get_property_any_by_id(
+        &self,
+        activity_id: &ActivityIdentifier,
+        property_name: &str,
+    ) -> Result<Arc<Mutex<DynamicPropertyAny>>> {
+        self.get_activity_by_id(activity_id)?
+            .lock()
+            .await
+            .get_property_any(property_name)
+    }
 }
@@ -0,0 +1,297 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use abi::{glib, module::ActivityIdentifier};
+use tokio::sync::Mutex;
+
+use crate::dynamic_activity::DynamicActivity;
+
+struct ScheduledActivity<A> {
+    activity: A,
+    priority: i32,
+    /// used to keep the scheduling order between activities with the same priority
+    order: u64,
+    shown: bool,
+    lifetime_source: Option<glib::SourceId>,
+}
+
+/// The changes needed to show only the activities with the highest priority
+///
+/// The activities in `to_hide` are already marked as hidden,
+/// the ones in `to_show` need to be confirmed with [`mark_shown`](ActivityScheduler::mark_shown) after they are registered
+pub(crate) struct SchedulerPlan<A> {
+    pub(crate) to_hide: Vec<ActivityIdentifier>,
+    pub(crate) to_show: Vec<(ActivityIdentifier, A)>,
+}
+
+impl<A> Default for SchedulerPlan<A> {
+    fn default() -> Self {
+        Self {
+            to_hide: Vec::new(),
+            to_show: Vec::new(),
+        }
+    }
+}
+
+/// Keeps the priority queue of the scheduled activities of a module
+pub(crate) struct ActivityScheduler<A = Rc<Mutex<DynamicActivity>>> {
+    entries: RefCell<HashMap<ActivityIdentifier, ScheduledActivity<A>>>,
+    max_shown: Cell<Option<usize>>,
+    next_order: Cell<u64>,
+}
+
+impl<A> Default for ActivityScheduler<A> {
+    fn default() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+            max_shown: Cell::new(None),
+            next_order: Cell::new(0),
+        }
+    }
+}
+
+impl<A: Clone> ActivityScheduler<A> {
+    pub(crate) fn set_max_shown(&self, max_shown: Option<usize>) {
+        self.max_shown.set(max_shown);
+    }
+
+    pub(crate) fn max_shown(&self) -> Option<usize> {
+        self.max_shown.get()
+    }
+
+    /// Add an activity to the queue, or update its priority and lifetime timer if it's already scheduled
+    pub(crate) fn insert(
+        &self,
+        activity_id: ActivityIdentifier,
+        activity: A,
+        priority: i32,
+        lifetime_source: Option<glib::SourceId>,
+    ) {
+        let mut entries = self.entries.borrow_mut();
+        if let Some(entry) = entries.get_mut(&activity_id) {
+            if let Some(source) = entry.lifetime_source.take() {
+                source.remove();
+            }
+            entry.activity = activity;
+            entry.priority = priority;
+            entry.lifetime_source = lifetime_source;
+            return;
+        }
+        let order = self.next_order.get();
+        self.next_order.set(order + 1);
+        entries.insert(
+            activity_id,
+            ScheduledActivity {
+                activity,
+                priority,
+                order,
+                shown: false,
+                lifetime_source,
+            },
+        );
+    }
+
+    /// Remove an activity from the queue and stop its lifetime timer
+    ///
+    /// returns whether the activity was shown, `None` if it wasn't scheduled
+    pub(crate) fn remove(&self, activity_id: &ActivityIdentifier) -> Option<bool> {
+        let entry = self.entries.borrow_mut().remove(activity_id)?;
+        if let Some(source) = entry.lifetime_source {
+            source.remove();
+        }
+        Some(entry.shown)
+    }
+
+    /// Forget the lifetime timer without removing it, used when the timer already fired
+    pub(crate) fn lifetime_ended(&self, activity_id: &ActivityIdentifier) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(activity_id) {
+            entry.lifetime_source = None;
+        }
+    }
+
+    /// Remove the activity from the queue if it was shown by the scheduler
+    ///
+    /// returns `true` if it was removed
+    pub(crate) fn remove_if_shown(&self, activity_id: &ActivityIdentifier) -> bool {
+        let shown = self
+            .entries
+            .borrow()
+            .get(activity_id)
+            .is_some_and(|entry| entry.shown);
+        if shown {
+            self.remove(activity_id);
+        }
+        shown
+    }
+
+    pub(crate) fn list(&self) -> Vec<(ActivityIdentifier, i32, bool)> {
+        let mut list: Vec<_> = self
+            .entries
+            .borrow()
+            .iter()
+            .map(|(id, entry)| (id.clone(), entry.priority, entry.order, entry.shown))
+            .collect();
+        list.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)));
+        list.into_iter()
+            .map(|(id, priority, _, shown)| (id, priority, shown))
+            .collect()
+    }
+
+    /// Get the activities to show and to hide so that only the top `max_shown` are shown
+    ///
+    /// The activities to hide are marked as hidden immediately, the ones to show stay hidden
+    /// until they are confirmed with [`mark_shown`](Self::mark_shown), so a failed registration is retried by the next plan
+    pub(crate) fn plan(&self) -> SchedulerPlan<A> {
+        let mut entries = self.entries.borrow_mut();
+        let mut sorted: Vec<_> = entries
+            .iter()
+            .map(|(id, entry)| (id.clone(), entry.priority, entry.order))
+            .collect();
+        sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.2.cmp(&b.2)));
+
+        let mut plan = SchedulerPlan::default();
+        for (i, (id, _, _)) in sorted.into_iter().enumerate() {
+            let should_show = self.max_shown.get().map_or(true, |max| i < max);
+            let entry = entries.get_mut(&id).unwrap();
+            if entry.shown && !should_show {
+                entry.shown = false;
+                plan.to_hide.push(id);
+            } else if !entry.shown && should_show {
+                plan.to_show.push((id, entry.activity.clone()));
+            }
+        }
+        plan
+    }
+
+    /// Mark an activity of the plan as shown after it was registered
+    ///
+    /// does nothing if it was unscheduled in the meantime
+    pub(crate) fn mark_shown(&self, activity_id: &ActivityIdentifier) {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(activity_id) {
+            entry.shown = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use abi::module::ActivityIdentifier;
+
+    use super::ActivityScheduler;
+
+    fn id(name: &str) -> ActivityIdentifier {
+        ActivityIdentifier::new("test", name)
+    }
+
+    fn schedule(scheduler: &ActivityScheduler<&'static str>, name: &'static str, priority: i32) {
+        scheduler.insert(id(name), name, priority, None);
+    }
+
+    /// Run the plan, confirming every activity to show
+    fn apply(scheduler: &ActivityScheduler<&'static str>) -> (Vec<String>, Vec<&'static str>) {
+        let plan = scheduler.plan();
+        for (id, _) in &plan.to_show {
+            scheduler.mark_shown(id);
+        }
+        (
+            plan.to_hide
+                .iter()
+                .map(|id| id.activity().to_string())
+                .collect(),
+            plan.to_show.into_iter().map(|(_, name)| name).collect(),
+        )
+    }
+
+    #[test]
+    fn highest_priorities_are_shown_in_scheduling_order() {
+        let scheduler = ActivityScheduler::default();
+        scheduler.set_max_shown(Some(2));
+        schedule(&scheduler, "low", 1);
+        schedule(&scheduler, "first", 5);
+        schedule(&scheduler, "second", 5);
+        schedule(&scheduler, "lowest", 0);
+
+        let (hidden, shown) = apply(&scheduler);
+        assert!(hidden.is_empty());
+        assert_eq!(shown, ["first", "second"]);
+        let list: Vec<_> = scheduler
+            .list()
+            .into_iter()
+            .map(|(id, priority, shown)| (id.activity().to_string(), priority, shown))
+            .collect();
+        assert_eq!(
+            list,
+            [
+                ("first".to_string(), 5, true),
+                ("second".to_string(), 5, true),
+                ("low".to_string(), 1, false),
+                ("lowest".to_string(), 0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn higher_priority_replaces_the_last_shown() {
+        let scheduler = ActivityScheduler::default();
+        scheduler.set_max_shown(Some(2));
+        schedule(&scheduler, "a", 3);
+        schedule(&scheduler, "b", 2);
+        schedule(&scheduler, "c", 1);
+        apply(&scheduler);
+
+        // scheduling again only updates the priority
+        schedule(&scheduler, "c", 10);
+        let (hidden, shown) = apply(&scheduler);
+        assert_eq!(hidden, ["b"]);
+        assert_eq!(shown, ["c"]);
+    }
+
+    #[test]
+    fn removing_a_shown_activity_promotes_the_next() {
+        let scheduler = ActivityScheduler::default();
+        scheduler.set_max_shown(Some(1));
+        schedule(&scheduler, "a", 2);
+        schedule(&scheduler, "b", 1);
+        apply(&scheduler);
+
+        assert!(!scheduler.remove_if_shown(&id("b")));
+        assert!(scheduler.remove_if_shown(&id("a")));
+        assert_eq!(scheduler.remove(&id("a")), None);
+        let (hidden, shown) = apply(&scheduler);
+        assert!(hidden.is_empty());
+        assert_eq!(shown, ["b"]);
+        assert_eq!(scheduler.remove(&id("b")), Some(true));
+    }
+
+    #[test]
+    fn unconfirmed_activities_are_planned_again() {
+        let scheduler = ActivityScheduler::default();
+        scheduler.set_max_shown(Some(1));
+        schedule(&scheduler, "a", 1);
+
+        // the registration failed, nothing is confirmed
+        assert_eq!(scheduler.plan().to_show.len(), 1);
+        assert!(!scheduler.list()[0].2);
+        let (_, shown) = apply(&scheduler);
+        assert_eq!(shown, ["a"]);
+        assert!(scheduler.plan().to_show.is_empty());
+    }
+
+    #[test]
+    fn no_limit_shows_everything() {
+        let scheduler = ActivityScheduler::default();
+        schedule(&scheduler, "a", 1);
+        schedule(&scheduler, "b", 2);
+        let (_, shown) = apply(&scheduler);
+        assert_eq!(shown.len(), 2);
+
+        scheduler.set_max_shown(Some(0));
+        let (mut hidden, shown) = apply(&scheduler);
+        hidden.sort();
+        assert_eq!(hidden, ["a", "b"]);
+        assert!(shown.is_empty());
+    }
+}
@@ -1,9 +1,15 @@
-use std::{collections::HashSet, rc::Rc, sync::Arc, thread};
+use std::{
+    collections::HashSet,
+    rc::Rc,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use abi::{abi_stable, glib, gtk, log};
 use abi_stable::external_types::crossbeam_channel::RSender;
-use anyhow::{anyhow, Context, Result};
-use dynisland_abi::module::UIServerCommand;
+use anyhow::{anyhow, bail, Context, Result};
+use dynisland_abi::module::{ActivityIdentifier, UIServerCommand};
 use glib::object::Cast;
 use tokio::{
     runtime::Handle,
@@ -15,7 +21,15 @@ use tokio::{
 };
 
 use crate::{
-    activity_map::ActivityMap, dynamic_activity::DynamicActivity, dynamic_property::PropertyUpdate,
+    activity_lifecycle::{ActivityLifecycleEvent, LifecycleHooks},
+    activity_map::ActivityMap,
+    activity_scheduler::ActivityScheduler,
+    dynamic_activity::DynamicActivity,
+    dynamic_property::PropertyUpdate,
+    graphics::activity_widget::{boxed_activity_mode::ActivityMode, ActivityWidget},
+    module_logger::{self, LogConfig},
+    registration_handle::RegistrationHandle,
+    transient_activity::{TransientActivities, TransientState},
 };
 
 pub type Producer<T> = fn(module: &T);
@@ -56,6 +70,12 @@ impl ProducerRuntime {
     pub fn handle(&self) -> Handle {
         self.handle.blocking_lock().clone()
     }
+    /// Get an handle to the tokio runtime
+    ///
+    /// Use this instead of `handle` from an async context
+    pub async fn handle_async(&self) -> Handle {
+        self.handle.lock().await.clone()
+    }
     /// Starts a new runtime, if the runtime is still running, it will stop without calling the cleanup_notifier
     pub async fn reset(&self) {
         let (handle, shutdown) = Self::get_new_tokio_rt();
@@ -163,6 +183,9 @@ pub struct BaseModule<T> {
     prop_send: UnboundedSender<PropertyUpdate>,
     registered_activities: Rc<Mutex<ActivityMap>>,
     registered_producers: Arc<Mutex<HashSet<Producer<T>>>>,
+    lifecycle_hooks: Rc<LifecycleHooks>,
+    transient_activities: Rc<TransientActivities>,
+    scheduler: Rc<ActivityScheduler>,
 }
 
 impl<T> Clone for BaseModule<T> {
@@ -173,6 +196,9 @@ impl<T> Clone for BaseModule<T> {
             prop_send: self.prop_send.clone(),
             registered_activities: self.registered_activities.clone(),
             registered_producers: self.registered_producers.clone(),
+            lifecycle_hooks: self.lifecycle_hooks.clone(),
+            transient_activities: self.transient_activities.clone(),
+            scheduler: self.scheduler.clone(),
         }
     }
 }
@@ -181,13 +207,18 @@ impl<T> BaseModule<T> {
     pub fn new(name: &'static str, app_send: RSender<UIServerCommand>) -> Self {
         let registered_activities = Rc::new(Mutex::new(ActivityMap::default()));
         let registered_producers = Arc::new(Mutex::new(HashSet::new()));
-        let prop_send = Self::spawn_property_update_loop(&registered_activities);
+        let transient_activities = Rc::new(TransientActivities::default());
+        let prop_send =
+            Self::spawn_property_update_loop(&registered_activities, &transient_activities);
         Self {
             name,
             app_send,
             prop_send,
             registered_activities,
             registered_producers,
+            lifecycle_hooks: Rc::new(LifecycleHooks::default()),
+            transient_activities,
+            scheduler: Rc::new(ActivityScheduler::default()),
         }
     }
     pub fn register_producer(&self, producer: Producer<T>) {
@@ -198,23 +229,33 @@ impl<T> BaseModule<T> {
         self.registered_producers.clone()
     }
 
+    /// Start every registered producer
+    ///
+    /// The producers should be stopped before calling this, for example with `ProducerRuntime::shutdown_blocking`
+    ///
+    /// blocking
+    pub fn run_producers(&self, module: &T) {
+        let producers = self.registered_producers.blocking_lock().clone();
+        for producer in producers {
+            producer(module);
+        }
+    }
+
     /// Register an activity with the app
     ///
     /// returns `Err` if the activity was already registered
+    ///
+    /// blocking, use `register_activity_async` from an async context
     pub fn register_activity(&self, activity: DynamicActivity) -> Result<()> {
-        let widget = activity.get_activity_widget();
-        let id = activity.get_identifier();
-        let activity = Rc::new(Mutex::new(activity));
+        self.register_activity_rc(Rc::new(Mutex::new(activity)))
+    }
 
-        self.app_send
-            .send(UIServerCommand::AddActivity {
-                activity_id: id,
-                widget: widget.upcast::<gtk::Widget>().into(),
-            })
-            .map_err(|err| anyhow!(err.to_string()))?;
-        let mut reg = self.registered_activities.blocking_lock();
-        reg.insert_activity(activity)
-            .with_context(|| "failed to register activity")
+    /// Register an activity with the app
+    ///
+    /// returns `Err` if the activity was already registered
+    pub async fn register_activity_async(&self, activity: DynamicActivity) -> Result<()> {
+        self.register_activity_rc_async(Rc::new(Mutex::new(activity)))
+            .await
     }
 
     /// Register an activity from an Rc<Mutex> with the app
@@ -223,21 +264,260 @@ impl<T> BaseModule<T> {
     /// To avoid creating multiple instances of the same dynamic activity
     ///
     /// returns `Err` if the activity was already registered
+    ///
+    /// blocking, use `register_activity_rc_async` from an async context
     pub fn register_activity_rc(&self, activity: Rc<Mutex<DynamicActivity>>) -> Result<()> {
         let activity_lock = activity.blocking_lock();
         let widget = activity_lock.get_activity_widget();
         let id = activity_lock.get_identifier();
         drop(activity_lock);
 
-        self.app_send
-            .send(UIServerCommand::AddActivity {
-                activity_id: id,
-                widget: widget.upcast::<gtk::Widget>().into(),
+        if self.registered_activities.blocking_lock().contains(&id) {
+            bail!("activity {id} was already registered");
+        }
+        self.send_add_activity(&id, &widget)?;
+        self.registered_activities
+            .blocking_lock()
+            .insert_activity_with_id(id.clone(), activity)
+            .with_context(|| "failed to register activity")?;
+        self.activity_registered(&id, &widget);
+        Ok(())
+    }
+
+    /// Register an activity from an Rc<Mutex> with the app
+    ///
+    /// This should be used when the activity is registered/unregistered multiple times during the module lifetime.
+    /// To avoid creating multiple instances of the same dynamic activity
+    ///
+    /// returns `Err` if the activity was already registered
+    pub async fn register_activity_rc_async(
+        &self,
+        activity: Rc<Mutex<DynamicActivity>>,
+    ) -> Result<()> {
+        let activity_lock = activity.lock().await;
+        let widget = activity_lock.get_activity_widget();
+        let id = activity_lock.get_identifier();
+        drop(activity_lock);
+
+        if self.registered_activities.lock().await.contains(&id) {
+            bail!("activity {id} was already registered");
+        }
+        self.send_add_activity(&id, &widget)?;
+        self.registered_activities
+            .lock()
+            .await
+            .insert_activity_with_id(id.clone(), activity)
+            .with_context(|| "failed to register activity")?;
+        self.activity_registered(&id, &widget);
+        Ok(())
+    }
+
+    /// Show an activity for a limited time
+    ///
+    /// Registers the activity (if it isn't already registered) and unregisters it after `duration`.
+    /// Every update of one of its dynamic properties extends the time, so it's unregistered `duration` after the last update.
+    /// Calling this again before the time runs out restarts the timer with the new duration.
+    ///
+    /// The same instance can be shown again after it was unregistered, like with `register_activity_rc`
+    ///
+    /// # Arguments
+    /// * `activity` - The activity to show
+    /// * `duration` - How long to show the activity
+    /// * `mode` - If `Some`, the mode of the activity is set to it
+    pub fn show_transient(
+        &self,
+        activity: Rc<Mutex<DynamicActivity>>,
+        duration: Duration,
+        mode: Option<ActivityMode>,
+    ) -> Result<()>
+    where
+        T: 'static,
+    {
+        self.show_transient_with_cleanup(activity, duration, mode, || {})
+    }
+
+    /// Show an activity for a limited time and call `cleanup` after it's unregistered
+    ///
+    /// `cleanup` is also called if the activity is unregistered manually before the time runs out.
+    /// If this is called again before the time runs out, the previous `cleanup` is dropped without being called
+    /// (the activity is still shown) and only the last one is called
+    ///
+    /// view [`show_transient`](Self::show_transient) for more info
+    pub fn show_transient_with_cleanup<F>(
+        &self,
+        activity: Rc<Mutex<DynamicActivity>>,
+        duration: Duration,
+        mode: Option<ActivityMode>,
+        cleanup: F,
+    ) -> Result<()>
+    where
+        T: 'static,
+        F: FnOnce() + 'static,
+    {
+        let activity_lock = activity.blocking_lock();
+        let widget = activity_lock.get_activity_widget();
+        let id = activity_lock.get_identifier();
+        drop(activity_lock);
+
+        match self.transient_activities.take(&id) {
+            Some(previous) => {
+                // still shown, only restart the timer, the previous cleanup is dropped
+                previous.source.remove();
+            }
+            None => {
+                if !self.registered_activities.blocking_lock().contains(&id) {
+                    self.register_activity_rc(activity)?;
+                }
+            }
+        }
+        if let Some(mode) = mode {
+            widget.set_mode(mode);
+        }
+
+        let source = self.start_transient_timer(id.clone(), duration);
+        self.transient_activities.insert(
+            id,
+            TransientState {
+                source,
+                cleanup: Some(Box::new(cleanup)),
+                duration,
+                deadline: Instant::now() + duration,
+            },
+        );
+        Ok(())
+    }
+
+    fn start_transient_timer(&self, id: ActivityIdentifier, delay: Duration) -> glib::SourceId
+    where
+        T: 'static,
+    {
+        let module = self.clone();
+        glib::timeout_add_local_once(delay, move || {
+            // remove the state first, it may be inserted again with a new timer
+            let Some(mut state) = module.transient_activities.take(&id) else {
+                return;
+            };
+            let now = Instant::now();
+            if state.deadline > now {
+                // the activity was updated after the timer started
+                state.source = module.start_transient_timer(id.clone(), state.deadline - now);
+                module.transient_activities.insert(id, state);
+                return;
+            }
+            module.unregister_by_id(&id);
+            if let Some(cleanup) = state.cleanup {
+                cleanup();
+            }
+        })
+    }
+
+    /// Check if the activity was shown with `show_transient` and its time didn't run out yet
+    pub fn is_transient_shown(&self, activity_id: &ActivityIdentifier) -> bool {
+        self.transient_activities.contains(activity_id)
+    }
+
+    /// Set how many scheduled activities can be shown at the same time, `None` for no limit
+    ///
+    /// view [`schedule_activity`](Self::schedule_activity) for more info
+    pub fn set_max_shown_activities(&self, max_shown: Option<usize>) {
+        self.scheduler.set_max_shown(max_shown);
+        self.rebalance_scheduled_activities();
+    }
+
+    /// Get how many scheduled activities can be shown at the same time, `None` if there is no limit
+    pub fn max_shown_activities(&self) -> Option<usize> {
+        self.scheduler.max_shown()
+    }
+
+    /// Add an activity to the priority queue of the module
+    ///
+    /// Only the scheduled activities with the highest priority (up to `set_max_shown_activities`) are registered with the app,
+    /// when one of them is unregistered or unscheduled the next one in the queue is registered.
+    /// Activities with the same priority are shown in the order they were scheduled.
+    ///
+    /// Scheduling an activity again updates its priority and restarts its lifetime.
+    ///
+    /// Activities registered with `register_activity` aren't counted
+    ///
+    /// # Arguments
+    /// * `activity` - The activity to schedule
+    /// * `priority` - Higher values are shown first
+    /// * `max_lifetime` - If `Some`, the activity is unscheduled after this time, even if it was never shown
+    pub fn schedule_activity(
+        &self,
+        activity: Rc<Mutex<DynamicActivity>>,
+        priority: i32,
+        max_lifetime: Option<Duration>,
+    ) where
+        T: 'static,
+    {
+        let id = activity.blocking_lock().get_identifier();
+        let lifetime_source = max_lifetime.map(|lifetime| {
+            let module = self.clone();
+            let timeout_id = id.clone();
+            glib::timeout_add_local_once(lifetime, move || {
+                module.scheduler.lifetime_ended(&timeout_id);
+                module.unschedule_activity(&timeout_id);
             })
-            .map_err(|err| anyhow!(err.to_string()))?;
-        let mut reg = self.registered_activities.blocking_lock();
-        reg.insert_activity(activity)
-            .with_context(|| "failed to register activity")
+        });
+        self.scheduler
+            .insert(id, activity, priority, lifetime_source);
+        self.rebalance_scheduled_activities();
+    }
+
+    /// Remove an activity from the priority queue, and unregister it if it was shown
+    ///
+    /// does nothing if the activity wasn't scheduled
+    pub fn unschedule_activity(&self, activity_id: &ActivityIdentifier) {
+        match self.scheduler.remove(activity_id) {
+            Some(true) => {
+                self.unregister_by_id(activity_id);
+                self.rebalance_scheduled_activities();
+            }
+            Some(false) => {}
+            None => {
+                log::debug!("activity {activity_id} isn't scheduled");
+            }
+        }
+    }
+
+    /// Get the scheduled activities sorted by priority, with their priority and whether they are shown
+    pub fn scheduled_activities(&self) -> Vec<(ActivityIdentifier, i32, bool)> {
+        self.scheduler.list()
+    }
+
+    fn rebalance_scheduled_activities(&self) {
+        let plan = self.scheduler.plan();
+        // hide first, so the app doesn't show more than the max for a moment
+        for id in plan.to_hide {
+            self.unregister_by_id(&id);
+        }
+        for (id, activity) in plan.to_show {
+            match self.register_activity_rc(activity) {
+                Ok(()) => self.scheduler.mark_shown(&id),
+                Err(err) => log::error!("failed to show scheduled activity {id}: {err:#}"),
+            }
+        }
+    }
+
+    /// Get a `Send` handle to register and unregister activities from the producers
+    ///
+    /// The requests are executed on the glib main context
+    pub fn registration_handle(&self) -> RegistrationHandle<T>
+    where
+        T: 'static,
+    {
+        RegistrationHandle::new(self.clone())
+    }
+
+    /// Add a callback for the lifecycle events of every activity registered with this module
+    ///
+    /// The `Visible` and `Hidden` events can be used to pause expensive work for activities that aren't shown
+    pub fn connect_activity_lifecycle<F>(&self, hook: F)
+    where
+        F: Fn(&ActivityIdentifier, ActivityLifecycleEvent) + 'static,
+    {
+        self.lifecycle_hooks.connect(Rc::new(hook));
     }
 
     /// Get a `Mutex` to the activity map
@@ -247,53 +527,165 @@ impl<T> BaseModule<T> {
 
     /// Unregister the activity with that name in the identifier
     ///
+    /// If more than one activity has the same name (for example on different windows) they are all unregistered,
+    /// use `unregister_by_id` to unregister a specific one
+    ///
     /// # Arguments
     /// * `activity_name` - The name of the activity (activity_identifier.activity())
     ///
     /// does nothing if the activity wasn't registered
+    ///
+    /// blocking, use `unregister_activity_async` from an async context
     pub fn unregister_activity(&self, activity_name: &str) {
-        let activity_list = self
-            .registered_activities()
+        let identifiers: Vec<ActivityIdentifier> = self
+            .registered_activities
             .blocking_lock()
-            .list_activities();
-        let identifier = activity_list.iter().find(|x| x.activity() == activity_name);
-        if identifier.is_none() {
+            .filter(|id| id.activity() == activity_name)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if identifiers.is_empty() {
             log::debug!("activity {activity_name} isn't registered");
             return;
         }
-        let identifier = identifier.unwrap().clone();
+        for identifier in identifiers {
+            self.unregister_by_id(&identifier);
+        }
+    }
+
+    /// Unregister the activity with that name in the identifier
+    ///
+    /// If more than one activity has the same name (for example on different windows) they are all unregistered,
+    /// use `unregister_by_id_async` to unregister a specific one
+    ///
+    /// # Arguments
+    /// * `activity_name` - The name of the activity (activity_identifier.activity())
+    ///
+    /// does nothing if the activity wasn't registered
+    pub async fn unregister_activity_async(&self, activity_name: &str) {
+        let identifiers: Vec<ActivityIdentifier> = self
+            .registered_activities
+            .lock()
+            .await
+            .filter(|id| id.activity() == activity_name)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if identifiers.is_empty() {
+            log::debug!("activity {activity_name} isn't registered");
+            return;
+        }
+        for identifier in identifiers {
+            self.unregister_by_id_async(&identifier).await;
+        }
+    }
+
+    /// Unregister the activity with this identifier, the metadata has to match too
+    ///
+    /// does nothing if the activity wasn't registered
+    ///
+    /// blocking, use `unregister_by_id_async` from an async context
+    pub fn unregister_by_id(&self, activity_id: &ActivityIdentifier) {
+        if !self
+            .registered_activities
+            .blocking_lock()
+            .contains(activity_id)
+        {
+            log::debug!("activity {activity_id} isn't registered");
+            return;
+        }
+        self.send_remove_activity(activity_id);
+        let res = self
+            .registered_activities
+            .blocking_lock()
+            .remove_activity(activity_id);
+        self.activity_unregistered(activity_id, res);
+    }
+
+    /// Unregister the activity with this identifier, the metadata has to match too
+    ///
+    /// does nothing if the activity wasn't registered
+    pub async fn unregister_by_id_async(&self, activity_id: &ActivityIdentifier) {
+        if !self
+            .registered_activities
+            .lock()
+            .await
+            .contains(activity_id)
+        {
+            log::debug!("activity {activity_id} isn't registered");
+            return;
+        }
+        self.send_remove_activity(activity_id);
+        let res = self
+            .registered_activities
+            .lock()
+            .await
+            .remove_activity(activity_id);
+        self.activity_unregistered(activity_id, res);
+    }
+
+    fn send_add_activity(&self, id: &ActivityIdentifier, widget: &ActivityWidget) -> Result<()> {
+        self.lifecycle_hooks
+            .emit(id, ActivityLifecycleEvent::BeforeRegister);
+        self.app_send
+            .send(UIServerCommand::AddActivity {
+                activity_id: id.clone(),
+                widget: widget.clone().upcast::<gtk::Widget>().into(),
+            })
+            .map_err(|err| anyhow!(err.to_string()))
+    }
+
+    fn activity_registered(&self, id: &ActivityIdentifier, widget: &ActivityWidget) {
+        self.lifecycle_hooks.track_visibility(id, widget);
+        self.lifecycle_hooks
+            .emit(id, ActivityLifecycleEvent::Registered);
+    }
+
+    fn send_remove_activity(&self, id: &ActivityIdentifier) {
+        self.lifecycle_hooks
+            .emit(id, ActivityLifecycleEvent::BeforeUnregister);
         self.app_send
             .send(UIServerCommand::RemoveActivity {
-                activity_id: identifier,
+                activity_id: id.clone(),
             })
             .unwrap_or_else(|err| log::debug!("err: {err}"));
+    }
 
-        match self
-            .registered_activities
-            .blocking_lock()
-            .map
-            .remove(activity_name)
-        {
-            Some(_) => {
-                log::trace!("activity {activity_name} unregistered from base module");
+    fn activity_unregistered(&self, id: &ActivityIdentifier, removed: Result<()>) {
+        match removed {
+            Ok(()) => {
+                log::trace!("activity {id} unregistered from base module");
             }
-            None => {
-                log::trace!("activity {activity_name} wasn't registered in base module");
+            Err(_) => {
+                log::trace!("activity {id} wasn't registered in base module");
             }
         }
+        self.lifecycle_hooks.untrack_visibility(id);
+        self.lifecycle_hooks
+            .emit(id, ActivityLifecycleEvent::Unregistered);
+        // unregistered manually before the time ran out
+        if let Some(state) = self.transient_activities.take(id) {
+            state.cancel();
+        }
+        // a scheduled activity was unregistered manually, show the next one
+        if self.scheduler.remove_if_shown(id) {
+            self.rebalance_scheduled_activities();
+        }
     }
 
     fn spawn_property_update_loop(
         registered_activities: &Rc<Mutex<ActivityMap>>,
+        transient_activities: &Rc<TransientActivities>,
     ) -> UnboundedSender<PropertyUpdate> {
         //create ui property update channel
         let (prop_send, mut prop_recv) = tokio::sync::mpsc::unbounded_channel::<PropertyUpdate>();
         let activities = registered_activities.clone();
+        let transients = transient_activities.clone();
         glib::MainContext::default().spawn_local(async move {
             //start data consumer
             while let Some(res) = prop_recv.recv().await {
                 if res.activity_id.activity() == "*" {
-                    for activity in activities.lock().await.map.values() {
+                    for (id, activity) in activities.lock().await.map.iter() {
+                        // an updated transient activity is shown for longer
+                        transients.extend(id);
                         match activity.lock().await.get_subscribers(&res.property_name) {
                             core::result::Result::Ok(subs) => {
                                 for sub in subs {
@@ -306,7 +698,8 @@ impl<T> BaseModule<T> {
                         }
                     }
                 } else {
-                    match activities.lock().await.map.get(res.activity_id.activity()) {
+                    transients.extend(&res.activity_id);
+                    match activities.lock().await.map.get(&res.activity_id) {
                         Some(activity) => {
                             match activity.lock().await.get_subscribers(&res.property_name) {
                                 core::result::Result::Ok(subs) => {
@@ -341,4 +734,12 @@ impl<T> BaseModule<T> {
     pub fn name(&self) -> &'static str {
         self.name
     }
+
+    /// Initialize the logger for this module, every record with a target in `target` is tagged with the module name
+    ///
+    /// `target` is usually `env!("CARGO_CRATE_NAME")`,
+    /// view [`module_logger::init`](crate::module_logger::init) for more info
+    pub fn init_logger(&self, target: &str, config: &LogConfig) -> Result<()> {
+        module_logger::init(self.name, target, config)
+    }
 }
@@ -0,0 +1,367 @@
+use std::fmt::Display;
+
+use serde::{ser, Serialize, Serializer};
+
+/// A typed value split at its structs, maps and options, used to merge configs without losing the enum variant names
+///
+/// `ron::Value` stores a unit variant as `()` and the other variants without their name,
+/// so it can't be deserialized back to a type with an enum field.
+/// Here every value that isn't merged is kept as the RON written by its `Serialize` implementation
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ConfigTree {
+    /// Any other value, in RON
+    Value(String),
+    Struct(Vec<(&'static str, ConfigTree)>),
+    /// The keys are in RON
+    Map(Vec<(String, ConfigTree)>),
+    Some(Box<ConfigTree>),
+}
+
+impl ConfigTree {
+    pub(crate) fn new<T: Serialize + ?Sized>(value: &T) -> Result<Self, ron::Error> {
+        match value.serialize(Splitter)? {
+            Some(tree) => Ok(tree),
+            None => Ok(ConfigTree::Value(ron::to_string(value)?)),
+        }
+    }
+
+    /// Deserialize the tree to `T`
+    pub(crate) fn parse<T: serde::de::DeserializeOwned>(&self) -> Result<T, ron::Error> {
+        ron::from_str(&self.to_string()).map_err(|err| err.code)
+    }
+
+    /// Replace the values of `self` with the ones of `over` that are in `mask`,
+    /// the `ron::Value` of the (partial) config that `over` was deserialized from
+    ///
+    /// Structs, maps and options are merged field by field, every other value in `over` replaces the one in `self`
+    pub(crate) fn merge(self, over: ConfigTree, mask: &ron::Value) -> ConfigTree {
+        match (self, over, mask) {
+            (
+                ConfigTree::Struct(mut fields),
+                ConfigTree::Struct(over_fields),
+                ron::Value::Map(mask),
+            ) => {
+                for (name, over_value) in over_fields {
+                    let Some(mask) = map_get(mask, &ron::Value::String(name.to_string())) else {
+                        // not in the user config, it has the default value
+                        continue;
+                    };
+                    match fields.iter_mut().find(|(field, _)| *field == name) {
+                        Some((_, value)) => replace_merged(value, over_value, Some(mask)),
+                        None => fields.push((name, over_value)),
+                    }
+                }
+                ConfigTree::Struct(fields)
+            }
+            (
+                ConfigTree::Map(mut entries),
+                ConfigTree::Map(over_entries),
+                ron::Value::Map(mask),
+            ) => {
+                // a map deserialized from the user config only contains the user entries
+                for (key, over_value) in over_entries {
+                    let key_mask = ron::from_str::<ron::Value>(&key)
+                        .ok()
+                        .and_then(|key| map_get(mask, &key));
+                    match entries.iter_mut().find(|(entry, _)| *entry == key) {
+                        Some((_, value)) => replace_merged(value, over_value, key_mask),
+                        None => entries.push((key, over_value)),
+                    }
+                }
+                ConfigTree::Map(entries)
+            }
+            (ConfigTree::Some(base), ConfigTree::Some(over), ron::Value::Option(Some(mask))) => {
+                ConfigTree::Some(Box::new(base.merge(*over, mask)))
+            }
+            (_, over, _) => over,
+        }
+    }
+}
+
+impl Display for ConfigTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigTree::Value(value) => write!(f, "{value}"),
+            ConfigTree::Struct(fields) => {
+                write!(f, "(")?;
+                for (name, value) in fields {
+                    write!(f, "{name}:{value},")?;
+                }
+                write!(f, ")")
+            }
+            ConfigTree::Map(entries) => {
+                write!(f, "{{")?;
+                for (key, value) in entries {
+                    write!(f, "{key}:{value},")?;
+                }
+                write!(f, "}}")
+            }
+            ConfigTree::Some(value) => write!(f, "Some({value})"),
+        }
+    }
+}
+
+fn map_get<'a>(map: &'a ron::Map, key: &ron::Value) -> Option<&'a ron::Value> {
+    map.iter()
+        .find_map(|(map_key, value)| (map_key == key).then_some(value))
+}
+
+/// Merge `over` into `value`, without a mask `over` replaces it
+fn replace_merged(value: &mut ConfigTree, over: ConfigTree, mask: Option<&ron::Value>) {
+    *value = match mask {
+        Some(mask) => std::mem::replace(value, ConfigTree::Value(String::new())).merge(over, mask),
+        None => over,
+    };
+}
+
+/// Serializer that builds the tree of the structs, maps and options,
+/// returns `None` for the other values, they are written by [`ConfigTree::new`]
+struct Splitter;
+
+impl Serializer for Splitter {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+    type SerializeSeq = Skip;
+    type SerializeTuple = Skip;
+    type SerializeTupleStruct = Skip;
+    type SerializeTupleVariant = Skip;
+    type SerializeMap = MapSplitter;
+    type SerializeStruct = StructSplitter;
+    type SerializeStructVariant = Skip;
+
+    fn serialize_bool(self, _: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_i8(self, _: i8) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_i16(self, _: i16) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_i32(self, _: i32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_i64(self, _: i64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_u8(self, _: u8) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_u16(self, _: u16) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_u32(self, _: u32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_u64(self, _: u64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_f32(self, _: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_f64(self, _: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_char(self, _: char) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_str(self, _: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ConfigTree::Some(Box::new(ConfigTree::new(value)?))))
+    }
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(Skip)
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(MapSplitter::default())
+    }
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(StructSplitter::default())
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(Skip)
+    }
+}
+
+#[derive(Default)]
+struct StructSplitter {
+    fields: Vec<(&'static str, ConfigTree)>,
+}
+
+impl ser::SerializeStruct for StructSplitter {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.fields.push((key, ConfigTree::new(value)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ConfigTree::Struct(self.fields)))
+    }
+}
+
+#[derive(Default)]
+struct MapSplitter {
+    entries: Vec<(String, ConfigTree)>,
+    key: Option<String>,
+}
+
+impl ser::SerializeMap for MapSplitter {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(ron::to_string(key)?);
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| <ron::Error as ser::Error>::custom("map value without a key"))?;
+        self.entries.push((key, ConfigTree::new(value)?));
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ConfigTree::Map(self.entries)))
+    }
+}
+
+/// The values that are never merged, their elements are ignored
+struct Skip;
+
+impl ser::SerializeSeq for Skip {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl ser::SerializeTuple for Skip {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl ser::SerializeTupleStruct for Skip {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl ser::SerializeTupleVariant for Skip {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &T) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+}
+
+impl ser::SerializeStructVariant for Skip {
+    type Ok = Option<ConfigTree>;
+    type Error = ron::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        _: &T,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+}
@@ -23,6 +23,8 @@ impl Clone for Box<dyn ValidDynamicClosure> {
 pub struct SubscribableProperty {
     pub(crate) property: Arc<Mutex<DynamicPropertyAny>>,
     pub(crate) subscribers: Vec<Box<dyn ValidDynamicClosure>>,
+    /// The first `bindings` subscribers were added by the bindings of an activity definition
+    pub(crate) bindings: usize,
 }
 
 /// Struct containing the `ActivityWidget`, the `ActivityIdentifier` and the dynamic properties of an activity
@@ -133,6 +135,7 @@ impl DynamicActivity {
         let subs_prop = SubscribableProperty {
             property: Arc::new(Mutex::new(prop)),
             subscribers: Vec::new(),
+            bindings: 0,
         };
         self.property_dictionary.insert(name.to_string(), subs_prop);
         Ok(())
@@ -153,6 +156,31 @@ impl DynamicActivity {
         Ok(())
     }
 
+    /// Add a subscriber for a binding of an activity definition,
+    /// it can be removed with `clear_binding_subscribers` without touching the ones added by the module
+    ///
+    /// Returns `Err` if the property doesn't exist
+    pub(crate) fn subscribe_binding<F>(&mut self, name: &str, callback: F) -> Result<()>
+    where
+        F: ValidDynamicClosure + 'static,
+    {
+        let prop = self
+            .property_dictionary
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("property {} doesn't exist on this activity", name))?;
+        prop.subscribers.insert(prop.bindings, Box::new(callback));
+        prop.bindings += 1;
+        Ok(())
+    }
+
+    /// Remove the subscribers added with `subscribe_binding`, used when the widgets are rebuilt
+    pub(crate) fn clear_binding_subscribers(&mut self) {
+        for property in self.property_dictionary.values_mut() {
+            property.subscribers.drain(..property.bindings);
+            property.bindings = 0;
+        }
+    }
+
     /// Get all of the subscribers for a property
     pub fn get_subscribers(&self, name: &str) -> Result<&[Box<dyn ValidDynamicClosure>]> {
         let prop = self
@@ -0,0 +1,488 @@
+use abi::{glib, gtk};
+use gtk::{
+    graphene::{Point, Rect},
+    prelude::*,
+    subclass::prelude::*,
+    TickCallbackId,
+};
+
+use super::{
+    transition,
+    transition_style::{Easing, TransitionEffect, TransitionStyle},
+    util, ActivityWidget,
+};
+
+/// Scale of the widgets at the start (incoming) or at the end (outgoing) of a zoom transition
+const ZOOM_IN_SCALE: f64 = 0.8;
+const ZOOM_OUT_SCALE: f64 = 1.2;
+
+/// The values of a mode widget drawn by `snapshot` when `config-css-transitions` is disabled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) struct ModeVisuals {
+    pub(super) opacity: f64,
+    pub(super) blur: f64,
+    pub(super) stretch: (f64, f64),
+    pub(super) translate: (f64, f64),
+    /// Offset of the slide effects along the short axis of the island,
+    /// vertical in a horizontal island and horizontal in a vertical one
+    pub(super) slide: f64,
+    /// Scale of the zoom effect, on top of the stretch
+    pub(super) zoom: f64,
+}
+
+impl ModeVisuals {
+    fn new(opacity: f64, blur: f64, stretch: (f64, f64), translate: (f64, f64)) -> Self {
+        Self {
+            opacity,
+            blur,
+            stretch: (
+                if stretch.0.is_finite() {
+                    stretch.0
+                } else {
+                    1.0
+                },
+                if stretch.1.is_finite() {
+                    stretch.1
+                } else {
+                    1.0
+                },
+            ),
+            translate,
+            slide: 0.0,
+            zoom: 1.0,
+        }
+    }
+
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        let lerp2 = |a: (f64, f64), b: (f64, f64)| (lerp(a.0, b.0), lerp(a.1, b.1));
+        Self {
+            opacity: lerp(self.opacity, other.opacity),
+            blur: lerp(self.blur, other.blur),
+            stretch: lerp2(self.stretch, other.stretch),
+            translate: lerp2(self.translate, other.translate),
+            slide: lerp(self.slide, other.slide),
+            zoom: lerp(self.zoom, other.zoom),
+        }
+    }
+}
+
+/// The values drawn by `snapshot`, with one [`ModeVisuals`] for each slot:
+/// the four built-in modes followed by the custom modes
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Visuals {
+    pub(super) size: (f64, f64),
+    /// Corner radius of the island shape
+    pub(super) radius: f64,
+    pub(super) modes: Vec<ModeVisuals>,
+}
+
+impl Visuals {
+    fn from_widget(obj: &ActivityWidget) -> Self {
+        let css_context = obj.imp().local_css_context.borrow();
+        let stretch_on_resize = css_context.get_stretch_on_resize();
+        let transform = |stretch: (f64, f64), translate: (f64, f64)| {
+            if stretch_on_resize {
+                (stretch, translate)
+            } else {
+                ((1.0, 1.0), (0.0, 0.0))
+            }
+        };
+        let size = css_context.get_size();
+        let modes = (0..css_context.get_slot_count())
+            .map(|slot| {
+                let (stretch, translate) = transform(
+                    css_context.get_stretch(slot),
+                    css_context.get_translate(slot),
+                );
+                ModeVisuals::new(
+                    css_context.get_opacity(slot),
+                    css_context.get_blur(slot),
+                    stretch,
+                    translate,
+                )
+            })
+            .collect();
+        let size = (size.0 as f64, size.1 as f64);
+        Self {
+            size,
+            radius: obj.imp().island_shape.borrow().radius(obj.mode(), size),
+            modes,
+        }
+    }
+
+    /// The slots that were added or removed since `self` are taken from `other`
+    fn lerp(&self, other: &Self, t: f64) -> Self {
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        Self {
+            size: (
+                lerp(self.size.0, other.size.0),
+                lerp(self.size.1, other.size.1),
+            ),
+            radius: lerp(self.radius, other.radius),
+            modes: other
+                .modes
+                .iter()
+                .enumerate()
+                .map(|(i, to)| match self.modes.get(i) {
+                    Some(from) => from.lerp(to, t),
+                    None => *to,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Animates the [`Visuals`] towards the values of the local css context on the frame clock
+pub(super) struct VisualsAnimation {
+    current: Option<Visuals>,
+    from: Option<Visuals>,
+    target: Option<Visuals>,
+    start: Option<i64>,
+    tick: Option<TickCallbackId>,
+    /// In microseconds
+    duration: i64,
+    easing: Easing,
+    /// The effect of the last mode transition and the outgoing slot,
+    /// the outgoing widget keeps its final position until the next one
+    effect: Option<(usize, TransitionEffect)>,
+    /// The idle callback that retargets the animation after a layout pass, view [`queue_retarget`]
+    retarget_source: Option<glib::SourceId>,
+}
+
+impl Default for VisualsAnimation {
+    fn default() -> Self {
+        let style = TransitionStyle::default();
+        Self {
+            current: None,
+            from: None,
+            target: None,
+            start: None,
+            tick: None,
+            duration: style.duration as i64 * 1000,
+            easing: style.easing,
+            effect: None,
+            retarget_source: None,
+        }
+    }
+}
+
+impl VisualsAnimation {
+    /// The values of the widget with the end position of the outgoing widget
+    fn target_from(&self, obj: &ActivityWidget) -> Visuals {
+        let mut target = Visuals::from_widget(obj);
+        let height = short_side(obj, target.size);
+        if let Some((outgoing, effect)) = self.effect {
+            let Some(outgoing) = target.modes.get_mut(outgoing) else {
+                return target;
+            };
+            match effect {
+                TransitionEffect::SlideUp => outgoing.slide = -height,
+                TransitionEffect::SlideDown => outgoing.slide = height,
+                TransitionEffect::Zoom => outgoing.zoom = ZOOM_OUT_SCALE,
+                TransitionEffect::Crossfade | TransitionEffect::MorphOnly => {}
+            }
+        }
+        target
+    }
+}
+
+/// The size to allocate now, `None` before the first measure
+pub(super) fn current_size(obj: &ActivityWidget) -> Option<(f64, f64)> {
+    obj.imp()
+        .visuals
+        .borrow()
+        .current
+        .as_ref()
+        .map(|current| current.size)
+}
+
+/// The corner radius of the island shape to draw now, `None` before the first measure
+pub(super) fn current_radius(obj: &ActivityWidget) -> Option<f64> {
+    obj.imp()
+        .visuals
+        .borrow()
+        .current
+        .as_ref()
+        .map(|current| current.radius)
+}
+
+/// Start animating towards the values of the local css context, or jump to them if `animate` is false
+///
+/// It doesn't do anything if `config-css-transitions` is enabled
+///
+/// returns `true` if the size was changed immediately, the caller needs to queue a resize
+pub(super) fn retarget(obj: &ActivityWidget, animate: bool) -> bool {
+    if obj.config_css_transitions() {
+        return false;
+    }
+    let mut animation = obj.imp().visuals.borrow_mut();
+    let target = animation.target_from(obj);
+    if animation.target.as_ref() == Some(&target) && (animate || animation.tick.is_none()) {
+        return false;
+    }
+    let size = target.size;
+    animation.target = Some(target.clone());
+    if !animate || animation.current.is_none() || !obj.is_mapped() {
+        let old_size = animation.current.as_ref().map(|current| current.size);
+        animation.current = Some(target);
+        let tick = animation.tick.take();
+        drop(animation);
+        if let Some(tick) = tick {
+            tick.remove();
+            // the running animation jumped to its end
+            transition::animation_ended(obj);
+        }
+        return old_size != Some(size);
+    }
+    animation.from = animation.current.clone();
+    animation.start = util::widget_frame_time(obj);
+    if animation.tick.is_some() {
+        return false;
+    }
+    drop(animation);
+
+    start_tick(obj);
+    false
+}
+
+/// Retarget the animation from an idle callback if the values of the local css context changed
+///
+/// Used by `measure`, which can find a new size but can't end the running transition during the layout
+pub(super) fn queue_retarget(obj: &ActivityWidget) {
+    if obj.config_css_transitions() {
+        return;
+    }
+    let mut animation = obj.imp().visuals.borrow_mut();
+    if animation.retarget_source.is_some()
+        || animation.target.as_ref() == Some(&animation.target_from(obj))
+    {
+        return;
+    }
+    let weak = obj.downgrade();
+    animation.retarget_source = Some(glib::idle_add_local_once(move || {
+        let Some(obj) = weak.upgrade() else {
+            return;
+        };
+        obj.imp().visuals.borrow_mut().retarget_source = None;
+        if retarget(&obj, true) {
+            obj.queue_resize();
+        }
+    }));
+}
+
+/// Animate the change from the slot `from` to the slot `to` with the duration, easing and effect of `style`
+///
+/// The local css context must already contain the values of the new mode
+///
+/// returns `true` if the size was changed immediately, the caller needs to queue a resize
+pub(super) fn start_transition(
+    obj: &ActivityWidget,
+    from: usize,
+    to: usize,
+    style: &TransitionStyle,
+) -> bool {
+    if obj.config_css_transitions() {
+        return false;
+    }
+    let mut animation = obj.imp().visuals.borrow_mut();
+    animation.duration = style.duration as i64 * 1000;
+    animation.easing = style.easing;
+    animation.effect = Some((from, style.effect));
+    let Some(current) = animation.current.clone() else {
+        drop(animation);
+        return retarget(obj, false);
+    };
+    let target = animation.target_from(obj);
+    // the slots without a current value (new custom modes) start from the target
+    let mut start = current.lerp(&target, 0.0);
+
+    // the incoming widget starts from the other side of the effect
+    let height = short_side(obj, target.size);
+    if let Some(incoming) = start.modes.get_mut(to) {
+        incoming.slide = match style.effect {
+            TransitionEffect::SlideUp => height,
+            TransitionEffect::SlideDown => -height,
+            _ => 0.0,
+        };
+        incoming.zoom = match style.effect {
+            TransitionEffect::Zoom => ZOOM_IN_SCALE,
+            _ => 1.0,
+        };
+    }
+    if style.effect == TransitionEffect::MorphOnly {
+        for (start, target) in start.modes.iter_mut().zip(&target.modes) {
+            start.opacity = target.opacity;
+            start.blur = target.blur;
+        }
+    }
+    animation.current = Some(start.clone());
+    animation.from = Some(start);
+    animation.target = Some(target);
+    animation.start = util::widget_frame_time(obj);
+    let mapped = obj.is_mapped();
+    let running = animation.tick.is_some();
+    drop(animation);
+
+    if !mapped {
+        return retarget(obj, false);
+    }
+    if !running {
+        start_tick(obj);
+    }
+    false
+}
+
+fn start_tick(obj: &ActivityWidget) {
+    let tick = obj.add_tick_callback(|obj, frame_clock| {
+        let now = util::frame_time(frame_clock);
+        let mut animation = obj.imp().visuals.borrow_mut();
+        let start = *animation.start.get_or_insert(now);
+        let (Some(from), Some(target)) = (animation.from.clone(), animation.target.clone()) else {
+            animation.tick = None;
+            return glib::ControlFlow::Break;
+        };
+        let t = if animation.duration > 0 {
+            ((now - start) as f64 / animation.duration as f64).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let next = from.lerp(&target, animation.easing.apply(t));
+        let size_changed =
+            animation.current.as_ref().map(|current| current.size) != Some(next.size);
+        animation.current = Some(next);
+        let finished = t >= 1.0;
+        if finished {
+            // the callback is removed by returning Break
+            animation.tick = None;
+        }
+        drop(animation);
+
+        if size_changed {
+            obj.queue_resize();
+        } else {
+            obj.queue_draw();
+        }
+        if finished {
+            transition::animation_ended(obj);
+            glib::ControlFlow::Break
+        } else {
+            glib::ControlFlow::Continue
+        }
+    });
+    obj.imp().visuals.borrow_mut().tick = Some(tick);
+}
+
+/// Whether the values are being animated on the frame clock
+pub(super) fn is_running(obj: &ActivityWidget) -> bool {
+    obj.imp().visuals.borrow().tick.is_some()
+}
+
+/// Stop the animation and forget the values, they are taken again from the local css context
+pub(super) fn reset(obj: &ActivityWidget) {
+    let animation = std::mem::take(&mut *obj.imp().visuals.borrow_mut());
+    if let Some(tick) = animation.tick {
+        tick.remove();
+    }
+    if let Some(source) = animation.retarget_source {
+        source.remove();
+    }
+}
+
+/// Forget the values of a slot that was removed, the next slots move back by one
+pub(super) fn remove_slot(obj: &ActivityWidget, slot: usize) {
+    let mut animation = obj.imp().visuals.borrow_mut();
+    let animation = &mut *animation;
+    for visuals in [
+        &mut animation.current,
+        &mut animation.from,
+        &mut animation.target,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if slot < visuals.modes.len() {
+            visuals.modes.remove(slot);
+        }
+    }
+    animation.effect = match animation.effect {
+        Some((outgoing, _)) if outgoing == slot => None,
+        Some((outgoing, effect)) if outgoing > slot => Some((outgoing - 1, effect)),
+        effect => effect,
+    };
+}
+
+/// The size of the island along its short axis, the distance moved by the slide effects
+fn short_side(obj: &ActivityWidget, size: (f64, f64)) -> f64 {
+    match obj.orientation() {
+        gtk::Orientation::Vertical => size.0,
+        _ => size.1,
+    }
+}
+
+/// Draw the children with the opacity, blur and transform of their mode
+pub(super) fn snapshot_children(obj: &ActivityWidget, snapshot: &gtk::Snapshot) {
+    let visuals = obj.imp().visuals.borrow().current.clone();
+    let vertical = obj.orientation() == gtk::Orientation::Vertical;
+    let mut child = obj.first_child();
+    while let Some(widget) = child {
+        child = widget.next_sibling();
+        let slot = obj.imp().slot_of_widget(&widget);
+        let mode_visuals = slot.and_then(|slot| visuals.as_ref()?.modes.get(slot).copied());
+        let Some(ModeVisuals {
+            opacity,
+            blur,
+            stretch: (stretch_x, stretch_y),
+            translate: (translate_x, translate_y),
+            slide,
+            zoom,
+        }) = mode_visuals
+        else {
+            // the background
+            obj.snapshot_child(&widget, snapshot);
+            continue;
+        };
+        if opacity <= 0.0 {
+            continue;
+        }
+        let Some(bounds) = widget.compute_bounds(obj) else {
+            continue;
+        };
+        let center = bounds.center();
+
+        snapshot.save();
+        if slide != 0.0 {
+            // the sliding widget is only visible inside the island
+            snapshot.push_clip(&Rect::new(
+                0.0,
+                0.0,
+                obj.width() as f32,
+                obj.height() as f32,
+            ));
+        }
+        // same as the css `transform: scale() translate()` with the origin in the center
+        let (slide_x, slide_y) = if vertical { (slide, 0.0) } else { (0.0, slide) };
+        snapshot.translate(&Point::new(
+            center.x() + slide_x as f32,
+            center.y() + slide_y as f32,
+        ));
+        snapshot.scale((stretch_x * zoom) as f32, (stretch_y * zoom) as f32);
+        snapshot.translate(&Point::new(
+            translate_x as f32 - center.x(),
+            translate_y as f32 - center.y(),
+        ));
+        snapshot.push_opacity(opacity);
+        if blur > 0.0 {
+            snapshot.push_blur(blur);
+        }
+        obj.snapshot_child(&widget, snapshot);
+        if blur > 0.0 {
+            snapshot.pop();
+        }
+        snapshot.pop();
+        if slide != 0.0 {
+            snapshot.pop();
+        }
+        snapshot.restore();
+    }
+}
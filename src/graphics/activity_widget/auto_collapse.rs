@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use abi::{glib, gtk};
+use gtk::{prelude::*, subclass::prelude::*, StateFlags};
+
+use super::{boxed_activity_mode::ActivityMode, ActivityWidget};
+
+/// Go to `target` after `timeout` without interactions in a mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AutoCollapseRule {
+    pub timeout: Duration,
+    pub target: ActivityMode,
+}
+
+/// The idle timer of the current mode
+#[derive(Default)]
+pub(super) struct AutoCollapse {
+    /// Per-mode overrides of `config-auto-collapse-timeout`, a zero timeout disables the timer
+    pub(super) rules: [Option<AutoCollapseRule>; 4],
+    source: Option<glib::SourceId>,
+    interacting: bool,
+}
+
+/// The states that pause the timer: pointer hover, focus inside the widget and an active press or drag
+const INTERACTION_FLAGS: StateFlags = StateFlags::PRELIGHT
+    .union(StateFlags::ACTIVE)
+    .union(StateFlags::FOCUS_WITHIN);
+
+/// Get the rule used in `mode`, the override if it's set,
+/// otherwise `config-auto-collapse-timeout` for the expanded and overlay modes
+pub(super) fn effective_rule(obj: &ActivityWidget, mode: ActivityMode) -> Option<AutoCollapseRule> {
+    if let Some(rule) = obj.imp().auto_collapse.borrow().rules[mode as usize] {
+        return (!rule.timeout.is_zero()).then_some(rule);
+    }
+    let timeout = obj.config_auto_collapse_timeout();
+    if timeout == 0 || !matches!(mode, ActivityMode::Expanded | ActivityMode::Overlay) {
+        return None;
+    }
+    let target = if obj.compact_mode_widget().is_some() {
+        ActivityMode::Compact
+    } else {
+        ActivityMode::Minimal
+    };
+    Some(AutoCollapseRule {
+        timeout: Duration::from_millis(timeout),
+        target,
+    })
+}
+
+/// Start the timer for the current mode from the beginning
+pub(super) fn restart(obj: &ActivityWidget) {
+    cancel(obj);
+    if obj.imp().auto_collapse.borrow().interacting {
+        return;
+    }
+    let mode = obj.mode();
+    let Some(rule) = effective_rule(obj, mode) else {
+        return;
+    };
+    if rule.target == mode {
+        return;
+    }
+    let weak = obj.downgrade();
+    let source = glib::timeout_add_local_once(rule.timeout, move || {
+        let Some(obj) = weak.upgrade() else {
+            return;
+        };
+        obj.imp().auto_collapse.borrow_mut().source = None;
+        if obj.mode() == mode {
+            obj.set_mode(rule.target);
+        }
+    });
+    obj.imp().auto_collapse.borrow_mut().source = Some(source);
+}
+
+pub(super) fn cancel(obj: &ActivityWidget) {
+    if let Some(source) = obj.imp().auto_collapse.borrow_mut().source.take() {
+        source.remove();
+    }
+}
+
+/// Pause the timer while the user interacts with the widget, and restart it after
+pub(super) fn connect_interactions(obj: &ActivityWidget) {
+    obj.connect_state_flags_changed(|obj, _| {
+        let interacting = obj.state_flags().intersects(INTERACTION_FLAGS);
+        let was_interacting = std::mem::replace(
+            &mut obj.imp().auto_collapse.borrow_mut().interacting,
+            interacting,
+        );
+        if interacting == was_interacting {
+            return;
+        }
+        if interacting {
+            cancel(obj);
+        } else {
+            restart(obj);
+        }
+    });
+}
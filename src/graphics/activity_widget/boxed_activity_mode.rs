@@ -1,9 +1,10 @@
-use std::{ffi::CString, fmt::Display};
+use std::{ffi::CString, fmt::Display, str::FromStr};
 
 use abi::glib;
 use glib::{ffi::GType, subclass::boxed::BoxedType, translate::FromGlib};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ActivityMode {
     Minimal = 0,
     Compact = 1,
@@ -11,6 +12,16 @@ pub enum ActivityMode {
     Overlay = 3,
 }
 
+impl ActivityMode {
+    /// The built-in modes, in the order of their slots in the ActivityWidget
+    pub const ALL: [ActivityMode; 4] = [
+        ActivityMode::Minimal,
+        ActivityMode::Compact,
+        ActivityMode::Expanded,
+        ActivityMode::Overlay,
+    ];
+}
+
 impl TryFrom<u8> for ActivityMode {
     type Error = String;
 
@@ -27,6 +38,23 @@ impl TryFrom<u8> for ActivityMode {
     }
 }
 
+impl FromStr for ActivityMode {
+    type Err = String;
+
+    /// Parse the mode from its name, ignoring the case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "minimal" => ActivityMode::Minimal,
+            "compact" => ActivityMode::Compact,
+            "expanded" => ActivityMode::Expanded,
+            "overlay" => ActivityMode::Overlay,
+            _ => {
+                return Err(format!("invalid name for ActivityMode: {s}"));
+            }
+        })
+    }
+}
+
 impl Display for ActivityMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let string = match self {
@@ -0,0 +1,340 @@
+use std::fmt::Display;
+
+use abi::gtk;
+use gtk::prelude::*;
+
+use super::{
+    boxed_activity_mode::ActivityMode, custom_mode::SizeRule, shape::IslandShape, ActivityWidget,
+};
+
+/// An invalid configuration found by [`ActivityWidgetBuilder::build`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ActivityWidgetBuildError {
+    /// Every ActivityWidget needs at least the minimal mode widget
+    MissingMinimalWidget,
+    /// The initial mode doesn't have a widget
+    MissingModeWidget(ActivityMode),
+    /// The same widget was used for more than one mode
+    SharedModeWidget(ActivityMode, ActivityMode),
+    /// The widget for this mode is already inside another widget
+    WidgetHasParent(ActivityMode),
+    /// The minimal size is negative
+    InvalidMinimalSize { width: i32, height: i32 },
+    /// The blur radius is negative or not finite
+    InvalidBlurRadius(f64),
+    /// The name of a custom mode is the name of a built-in mode or of another custom mode
+    DuplicateModeName(String),
+    /// The widget of this custom mode is already inside another widget or used by another mode
+    CustomWidgetHasParent(String),
+}
+
+impl Display for ActivityWidgetBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingMinimalWidget => write!(f, "the minimal mode widget is required"),
+            Self::MissingModeWidget(mode) => {
+                write!(
+                    f,
+                    "the initial mode is {mode} but there is no {mode} widget"
+                )
+            }
+            Self::SharedModeWidget(first, second) => write!(
+                f,
+                "the same widget is used for the {first} and {second} modes"
+            ),
+            Self::WidgetHasParent(mode) => {
+                write!(f, "the {mode} widget already has a parent")
+            }
+            Self::InvalidMinimalSize { width, height } => {
+                write!(f, "invalid minimal size: {width}x{height}")
+            }
+            Self::InvalidBlurRadius(radius) => write!(f, "invalid blur radius: {radius}"),
+            Self::DuplicateModeName(name) => write!(f, "the mode name {name} is already used"),
+            Self::CustomWidgetHasParent(name) => {
+                write!(f, "the widget of the custom mode {name} is already used")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ActivityWidgetBuildError {}
+
+/// A builder for [`ActivityWidget`] that sets the properties in the right order
+///
+/// The config values are set first, then the mode widgets, the css classes and the initial mode.
+///
+/// # Example
+/// ```ignore
+/// let widget = ActivityWidget::builder()
+///     .name("music-activity")
+///     .minimal_widget(&minimal)
+///     .compact_widget(&compact)
+///     .mode(ActivityMode::Compact)
+///     .css_class("music")
+///     .build()?;
+/// ```
+#[derive(Clone, Debug, Default)]
+#[must_use = "the builder doesn't do anything until `build` is called"]
+pub struct ActivityWidgetBuilder {
+    name: Option<String>,
+    mode_widgets: [Option<gtk::Widget>; 4],
+    custom_modes: Vec<(String, gtk::Widget, ActivityMode, SizeRule)>,
+    mode: Option<ActivityMode>,
+    orientation: Option<gtk::Orientation>,
+    config_minimal_height: Option<i32>,
+    config_minimal_width: Option<i32>,
+    config_blur_radius: Option<f64>,
+    config_enable_drag_stretch: Option<bool>,
+    config_drag_stretch_stiffness: Option<f64>,
+    config_drag_stretch_threshold: Option<f64>,
+    config_auto_collapse_timeout: Option<u64>,
+    config_css_transitions: Option<bool>,
+    island_shape: Option<IslandShape>,
+    css_classes: Vec<String>,
+}
+
+impl ActivityWidgetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The name of the widget, used in the css
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn minimal_widget(self, widget: &impl IsA<gtk::Widget>) -> Self {
+        self.mode_widget(ActivityMode::Minimal, widget)
+    }
+
+    pub fn compact_widget(self, widget: &impl IsA<gtk::Widget>) -> Self {
+        self.mode_widget(ActivityMode::Compact, widget)
+    }
+
+    pub fn expanded_widget(self, widget: &impl IsA<gtk::Widget>) -> Self {
+        self.mode_widget(ActivityMode::Expanded, widget)
+    }
+
+    pub fn overlay_widget(self, widget: &impl IsA<gtk::Widget>) -> Self {
+        self.mode_widget(ActivityMode::Overlay, widget)
+    }
+
+    /// Set the widget for a mode
+    pub fn mode_widget(mut self, mode: ActivityMode, widget: &impl IsA<gtk::Widget>) -> Self {
+        self.mode_widgets[mode as usize] = Some(widget.clone().upcast());
+        self
+    }
+
+    /// Add a named mode, view [`ActivityWidget::add_custom_mode`]
+    pub fn custom_mode(
+        mut self,
+        name: &str,
+        widget: &impl IsA<gtk::Widget>,
+        base: ActivityMode,
+        size_rule: SizeRule,
+    ) -> Self {
+        self.custom_modes
+            .push((name.to_string(), widget.clone().upcast(), base, size_rule));
+        self
+    }
+
+    /// The initial mode, defaults to `Minimal`
+    pub fn mode(mut self, mode: ActivityMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// The direction of the island, defaults to `Horizontal`
+    pub fn orientation(mut self, orientation: gtk::Orientation) -> Self {
+        self.orientation = Some(orientation);
+        self
+    }
+
+    pub fn config_minimal_height(mut self, height: i32) -> Self {
+        self.config_minimal_height = Some(height);
+        self
+    }
+
+    pub fn config_minimal_width(mut self, width: i32) -> Self {
+        self.config_minimal_width = Some(width);
+        self
+    }
+
+    pub fn config_blur_radius(mut self, radius: f64) -> Self {
+        self.config_blur_radius = Some(radius);
+        self
+    }
+
+    pub fn config_enable_drag_stretch(mut self, enable: bool) -> Self {
+        self.config_enable_drag_stretch = Some(enable);
+        self
+    }
+
+    pub fn config_drag_stretch_stiffness(mut self, stiffness: f64) -> Self {
+        self.config_drag_stretch_stiffness = Some(stiffness);
+        self
+    }
+
+    pub fn config_drag_stretch_threshold(mut self, threshold: f64) -> Self {
+        self.config_drag_stretch_threshold = Some(threshold);
+        self
+    }
+
+    /// Milliseconds without interactions before the expanded and overlay modes collapse, 0 disables it
+    pub fn config_auto_collapse_timeout(mut self, timeout: u64) -> Self {
+        self.config_auto_collapse_timeout = Some(timeout);
+        self
+    }
+
+    pub fn config_css_transitions(mut self, enabled: bool) -> Self {
+        self.config_css_transitions = Some(enabled);
+        self
+    }
+
+    /// The shape used to clip the island, defaults to the css `border-radius`
+    pub fn island_shape(mut self, shape: IslandShape) -> Self {
+        self.island_shape = Some(shape);
+        self
+    }
+
+    pub fn css_class(mut self, class: &str) -> Self {
+        self.css_classes.push(class.to_string());
+        self
+    }
+
+    pub fn css_classes<I, S>(mut self, classes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.css_classes
+            .extend(classes.into_iter().map(|class| class.as_ref().to_string()));
+        self
+    }
+
+    /// Validate the configuration and build the widget
+    ///
+    /// returns `Err` if there is no minimal widget, if the initial mode doesn't have a widget,
+    /// if a widget is used twice or already has a parent, if a mode name is used twice
+    /// or if a config value is invalid
+    pub fn build(self) -> Result<ActivityWidget, ActivityWidgetBuildError> {
+        self.validate()?;
+
+        let widget = match &self.name {
+            Some(name) => ActivityWidget::new(name),
+            None => ActivityWidget::default(),
+        };
+        if let Some(orientation) = self.orientation {
+            widget.set_orientation(orientation);
+        }
+        if let Some(height) = self.config_minimal_height {
+            widget.set_config_minimal_height(height);
+        }
+        if let Some(width) = self.config_minimal_width {
+            widget.set_config_minimal_width(width);
+        }
+        if let Some(radius) = self.config_blur_radius {
+            widget.set_config_blur_radius(radius);
+        }
+        if let Some(enable) = self.config_enable_drag_stretch {
+            widget.set_config_enable_drag_stretch(enable);
+        }
+        if let Some(stiffness) = self.config_drag_stretch_stiffness {
+            widget.set_config_drag_stretch_stiffness(stiffness);
+        }
+        if let Some(threshold) = self.config_drag_stretch_threshold {
+            widget.set_config_drag_stretch_threshold(threshold);
+        }
+        if let Some(enabled) = self.config_css_transitions {
+            widget.set_config_css_transitions(enabled);
+        }
+        if let Some(shape) = self.island_shape {
+            widget.set_island_shape(shape);
+        }
+        if let Some(timeout) = self.config_auto_collapse_timeout {
+            widget.set_config_auto_collapse_timeout(timeout);
+        }
+        for (mode, mode_widget) in ActivityMode::ALL.into_iter().zip(self.mode_widgets) {
+            let Some(mode_widget) = mode_widget else {
+                continue;
+            };
+            match mode {
+                ActivityMode::Minimal => widget.set_minimal_mode_widget(mode_widget),
+                ActivityMode::Compact => widget.set_compact_mode_widget(mode_widget),
+                ActivityMode::Expanded => widget.set_expanded_mode_widget(mode_widget),
+                ActivityMode::Overlay => widget.set_overlay_mode_widget(mode_widget),
+            }
+        }
+        for (name, custom_widget, base, size_rule) in self.custom_modes.iter() {
+            // already validated
+            widget
+                .add_custom_mode(name, custom_widget, *base, *size_rule)
+                .unwrap();
+        }
+        for class in self.css_classes.iter() {
+            widget.add_css_class(class);
+        }
+        widget.set_mode(self.mode.unwrap_or(ActivityMode::Minimal));
+        Ok(widget)
+    }
+
+    fn validate(&self) -> Result<(), ActivityWidgetBuildError> {
+        if self.mode_widgets[ActivityMode::Minimal as usize].is_none() {
+            return Err(ActivityWidgetBuildError::MissingMinimalWidget);
+        }
+        let mode = self.mode.unwrap_or(ActivityMode::Minimal);
+        if self.mode_widgets[mode as usize].is_none() {
+            return Err(ActivityWidgetBuildError::MissingModeWidget(mode));
+        }
+        for (i, (mode, mode_widget)) in ActivityMode::ALL
+            .into_iter()
+            .zip(&self.mode_widgets)
+            .enumerate()
+        {
+            let Some(mode_widget) = mode_widget else {
+                continue;
+            };
+            if mode_widget.parent().is_some() {
+                return Err(ActivityWidgetBuildError::WidgetHasParent(mode));
+            }
+            for (other_mode, other) in ActivityMode::ALL
+                .into_iter()
+                .zip(&self.mode_widgets)
+                .skip(i + 1)
+            {
+                if other.as_ref() == Some(mode_widget) {
+                    return Err(ActivityWidgetBuildError::SharedModeWidget(mode, other_mode));
+                }
+            }
+        }
+        for (i, (name, custom_widget, _, _)) in self.custom_modes.iter().enumerate() {
+            if name.parse::<ActivityMode>().is_ok()
+                || self.custom_modes[..i].iter().any(|other| &other.0 == name)
+            {
+                return Err(ActivityWidgetBuildError::DuplicateModeName(name.clone()));
+            }
+            if custom_widget.parent().is_some()
+                || self.mode_widgets.contains(&Some(custom_widget.clone()))
+                || self.custom_modes[..i]
+                    .iter()
+                    .any(|other| &other.1 == custom_widget)
+            {
+                return Err(ActivityWidgetBuildError::CustomWidgetHasParent(
+                    name.clone(),
+                ));
+            }
+        }
+        let width = self.config_minimal_width.unwrap_or(0);
+        let height = self.config_minimal_height.unwrap_or(0);
+        if width < 0 || height < 0 {
+            return Err(ActivityWidgetBuildError::InvalidMinimalSize { width, height });
+        }
+        if let Some(radius) = self.config_blur_radius {
+            if !radius.is_finite() || radius < 0.0 {
+                return Err(ActivityWidgetBuildError::InvalidBlurRadius(radius));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use abi::gtk;
+
+use super::boxed_activity_mode::ActivityMode;
+
+/// How the size of a mode widget is decided
+///
+/// view [`get_final_widget_size`](super::util::get_final_widget_size) for more info
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeRule {
+    /// The size is `config-minimal-width` x `config-minimal-height`, like the minimal mode
+    Minimal,
+    /// The height is `config-minimal-height` and the width is requested by the widget, like the compact mode.
+    /// In a vertical island the width is forced instead
+    MinimalHeight,
+    /// The size is requested by the widget, like the expanded and overlay modes
+    #[default]
+    Natural,
+    /// A fixed size, it's still at least the minimal size
+    Fixed { width: i32, height: i32 },
+}
+
+impl From<ActivityMode> for SizeRule {
+    fn from(mode: ActivityMode) -> Self {
+        match mode {
+            ActivityMode::Minimal => SizeRule::Minimal,
+            ActivityMode::Compact => SizeRule::MinimalHeight,
+            ActivityMode::Expanded | ActivityMode::Overlay => SizeRule::Natural,
+        }
+    }
+}
+
+/// Whether `name` can be used in the css classes of a custom mode (`mode-<name>` and `in-<name>-mode`),
+/// it needs to match `[A-Za-z_-][A-Za-z0-9_-]*`
+pub(super) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == '-')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// A mode of the ActivityWidget
+///
+/// The built-in modes are the slots 0-3, in the order of [`ActivityMode::ALL`],
+/// the custom modes added with [`add_custom_mode`](super::ActivityWidget::add_custom_mode) follow in the order they were added
+#[derive(Clone, Debug)]
+pub(super) struct ModeSlot {
+    pub(super) name: String,
+    /// Always set for the custom modes
+    pub(super) widget: Option<gtk::Widget>,
+    /// The value of the `mode` property while this mode is shown
+    pub(super) base: ActivityMode,
+    pub(super) size_rule: SizeRule,
+}
+
+impl ModeSlot {
+    pub(super) fn builtin(mode: ActivityMode) -> Self {
+        Self {
+            name: mode.to_string(),
+            widget: None,
+            base: mode,
+            size_rule: mode.into(),
+        }
+    }
+
+    pub(super) fn custom(
+        name: &str,
+        widget: gtk::Widget,
+        base: ActivityMode,
+        size_rule: SizeRule,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            widget: Some(widget),
+            base,
+            size_rule,
+        }
+    }
+
+    /// The css class of the widget of this mode
+    pub(super) fn widget_class(&self) -> String {
+        format!("mode-{}", self.name)
+    }
+
+    /// The css class of the ActivityWidget while this mode is shown
+    pub(super) fn class(&self) -> String {
+        format!("in-{}-mode", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_name;
+
+    #[test]
+    fn names_must_be_css_identifiers() {
+        for name in ["alert", "now_playing", "-timer", "_x", "call-2"] {
+            assert!(is_valid_name(name), "{name} should be valid");
+        }
+        for name in ["", "2fa", "now playing", "a.b", "alert!", "mode>x", "émoji"] {
+            assert!(!is_valid_name(name), "{name} should be invalid");
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::cell::Cell;
+
+use abi::{glib, gtk};
+use gtk::{prelude::*, subclass::prelude::*, TickCallbackId};
+
+use super::{animation, gesture, imp::ActivityWidgetPriv, util, ActivityWidget};
+
+/// Stiffness of the spring that brings the widget back to its size after a drag, in 1/s²
+const SPRING_STIFFNESS: f64 = 300.0;
+/// Below 1 the spring overshoots a little before settling
+const SPRING_DAMPING_RATIO: f64 = 0.75;
+/// The spring is considered settled below this offset (px) and velocity (px/s)
+const REST_OFFSET: f64 = 0.5;
+const REST_VELOCITY: f64 = 5.0;
+/// Longer frames are split in steps of this length (s) to keep the integration stable
+const MAX_STEP: f64 = 1.0 / 120.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Idle,
+    Dragging,
+    /// The user released the widget and the spring is bringing it back
+    Releasing,
+}
+
+/// The state of the drag stretch
+///
+/// The `ActivityWidget` has the `dragging` class from the start of the drag until the spring settles
+#[derive(Default)]
+pub(super) struct DragStretch {
+    phase: Phase,
+    /// The offset of a spring that was still moving when a new drag started
+    base: (f64, f64),
+    /// The current size offset in pixels, positive when the widget grows
+    offset: (f64, f64),
+    velocity: (f64, f64),
+    tick: Option<TickCallbackId>,
+}
+
+/// Map the drag offset to the size offset with a rubber-band resistance
+///
+/// The widget follows the pointer at first, then it slows down and never grows more than `size / stiffness`.
+/// If `stiffness` is 0 or negative the offset is linear
+pub(super) fn rubber_band(offset: f64, size: f64, stiffness: f64) -> f64 {
+    if stiffness <= 0.0 || size <= 0.0 {
+        return offset;
+    }
+    let limit = size / stiffness;
+    offset.signum() * limit * (1.0 - 1.0 / (1.0 + offset.abs() / limit))
+}
+
+pub(super) fn begin(obj: &ActivityWidget, x: f64, y: f64) {
+    let mut stretch = obj.imp().drag_stretch.borrow_mut();
+    // a new drag catches the widget where the spring left it
+    stretch.base = if stretch.phase == Phase::Releasing {
+        stretch.offset
+    } else {
+        (0.0, 0.0)
+    };
+    stretch.phase = Phase::Dragging;
+    let tick = stretch.tick.take();
+    drop(stretch);
+    if let Some(tick) = tick {
+        tick.remove();
+    }
+    obj.add_css_class("dragging");
+    obj.emit_by_name::<()>("drag-stretch-begin", &[&x, &y]);
+}
+
+/// `x` and `y` are the drag offset, positive when dragging away from the center
+pub(super) fn update(obj: &ActivityWidget, x: f64, y: f64) {
+    if obj.imp().drag_stretch.borrow().phase != Phase::Dragging {
+        return;
+    }
+    let size = current_size(obj);
+    let stiffness = obj.config_drag_stretch_stiffness();
+    let base = obj.imp().drag_stretch.borrow().base;
+    let offset = (
+        base.0 + rubber_band(x, size.0, stiffness),
+        base.1 + rubber_band(y, size.1, stiffness),
+    );
+    obj.imp().drag_stretch.borrow_mut().offset = offset;
+    apply_offset(obj, offset);
+    obj.emit_by_name::<()>("drag-stretch-update", &[&x, &y]);
+}
+
+/// Release the widget, `None` if the drag was cancelled
+///
+/// If `config-drag-stretch-threshold` is set and the drag went past it,
+/// the widget goes to the next (or previous) mode, otherwise it springs back
+pub(super) fn end(obj: &ActivityWidget, offset: Option<(f64, f64)>) {
+    if obj.imp().drag_stretch.borrow().phase != Phase::Dragging {
+        return;
+    }
+    let threshold = obj.config_drag_stretch_threshold();
+    let target = match offset {
+        Some((x, y)) if threshold > 0.0 => {
+            if x.max(y) >= threshold {
+                gesture::adjacent_mode(obj, true)
+            } else if x.min(y) <= -threshold {
+                gesture::adjacent_mode(obj, false)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+    // a swipe on the same release may have already changed the mode
+    .filter(|_| gesture::claim_mode_change(obj));
+    let (x, y) = offset.unwrap_or((0.0, 0.0));
+
+    if let Some(target) = target {
+        // the mode transition replaces the spring
+        stop(obj);
+        apply_offset(obj, (0.0, 0.0));
+        obj.emit_by_name::<()>("drag-stretch-end", &[&x, &y]);
+        obj.set_mode(target);
+    } else {
+        spring_back(obj);
+        obj.emit_by_name::<()>("drag-stretch-end", &[&x, &y]);
+    }
+}
+
+/// Stop the drag or the spring without changing the css
+pub(super) fn stop(obj: &ActivityWidget) {
+    let tick = std::mem::take(&mut *obj.imp().drag_stretch.borrow_mut()).tick;
+    if let Some(tick) = tick {
+        tick.remove();
+    }
+    obj.remove_css_class("dragging");
+}
+
+/// Whether the spring is bringing the widget back after a drag
+pub(super) fn is_releasing(obj: &ActivityWidget) -> bool {
+    obj.imp().drag_stretch.borrow().phase == Phase::Releasing
+}
+
+fn spring_back(obj: &ActivityWidget) {
+    {
+        let mut stretch = obj.imp().drag_stretch.borrow_mut();
+        stretch.phase = Phase::Releasing;
+        stretch.velocity = (0.0, 0.0);
+    }
+    if !obj.is_mapped() {
+        // there is no frame clock
+        settle(obj);
+        return;
+    }
+    let damping = 2.0 * SPRING_DAMPING_RATIO * SPRING_STIFFNESS.sqrt();
+    let last_time = Cell::new(None);
+    let tick = obj.add_tick_callback(move |obj, frame_clock| {
+        let now = util::frame_time(frame_clock);
+        let dt = (now - last_time.replace(Some(now)).unwrap_or(now)) as f64 / 1_000_000.0;
+
+        let mut stretch = obj.imp().drag_stretch.borrow_mut();
+        let (mut offset, mut velocity) = (stretch.offset, stretch.velocity);
+        let steps = (dt / MAX_STEP).ceil().max(1.0);
+        let step = dt / steps;
+        for _ in 0..steps as u32 {
+            velocity.0 += (-SPRING_STIFFNESS * offset.0 - damping * velocity.0) * step;
+            velocity.1 += (-SPRING_STIFFNESS * offset.1 - damping * velocity.1) * step;
+            offset.0 += velocity.0 * step;
+            offset.1 += velocity.1 * step;
+        }
+        stretch.offset = offset;
+        stretch.velocity = velocity;
+        drop(stretch);
+
+        let settled = offset.0.abs().max(offset.1.abs()) < REST_OFFSET
+            && velocity.0.abs().max(velocity.1.abs()) < REST_VELOCITY;
+        if !settled {
+            apply_offset(obj, offset);
+            return glib::ControlFlow::Continue;
+        }
+        obj.imp().drag_stretch.borrow_mut().tick = None;
+        settle(obj);
+        glib::ControlFlow::Break
+    });
+    obj.imp().drag_stretch.borrow_mut().tick = Some(tick);
+}
+
+fn settle(obj: &ActivityWidget) {
+    stop(obj);
+    apply_offset(obj, (0.0, 0.0));
+}
+
+fn current_size(obj: &ActivityWidget) -> (f64, f64) {
+    ActivityWidgetPriv::get_current_size(
+        obj,
+        obj.config_minimal_height(),
+        obj.config_minimal_width(),
+    )
+}
+
+/// Stretch the current mode widget by `offset` pixels, `(0, 0)` restores the normal size
+fn apply_offset(obj: &ActivityWidget, offset: (f64, f64)) {
+    let min_height = obj.config_minimal_height();
+    let min_width = obj.config_minimal_width();
+    let size = current_size(obj);
+    // the minimal size of a vertical island is swapped
+    let min_size = if obj.orientation() == gtk::Orientation::Vertical {
+        (min_height, min_width)
+    } else {
+        (min_width, min_height)
+    };
+    let mut css_context = obj.imp().local_css_context.borrow_mut();
+    if offset == (0.0, 0.0) {
+        let stretches = ActivityWidgetPriv::get_stretches(obj, size, min_height, min_width);
+        css_context.set_stretch_all(&stretches, None);
+        css_context.set_size((size.0 as i32, size.1 as i32));
+    } else {
+        let next_size = (
+            (size.0 + offset.0).max(min_size.0 as f64),
+            (size.1 + offset.1).max(min_size.1 as f64),
+        );
+        let mut stretches =
+            ActivityWidgetPriv::get_stretches(obj, next_size, min_height, min_width);
+        stretches[obj.imp().current_slot()] = (next_size.0 / size.0, next_size.1 / size.1);
+        let translates = ActivityWidgetPriv::get_translates(obj, next_size, &stretches, min_height);
+        css_context.set_stretch_all(&stretches, Some(&translates));
+        css_context.set_size((next_size.0 as i32, next_size.1 as i32));
+    }
+    drop(css_context);
+    // the drag is followed without animations
+    if animation::retarget(obj, false) {
+        obj.queue_resize();
+    }
+    obj.queue_draw();
+}
@@ -0,0 +1,279 @@
+use std::{collections::HashMap, time::Duration};
+
+use abi::{gdk, glib, gtk};
+use gtk::{prelude::*, subclass::prelude::*, EventControllerScrollFlags};
+
+use super::{boxed_activity_mode::ActivityMode, ActivityWidget};
+
+/// Minimum velocity of a swipe along the short axis of the island, in pixels per second.
+/// Slower drags are only used for stretching
+const SWIPE_MIN_VELOCITY: f64 = 800.0;
+
+/// An input on the ActivityWidget that can be mapped to a [`GestureAction`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    /// A primary button click, delayed by the double click time if `DoubleClick` is mapped
+    Click,
+    DoubleClick,
+    LongPress,
+    /// A fast drag along the short axis of the island (vertical, or horizontal in a vertical island),
+    /// slow drags still stretch the widget.
+    /// In a vertical island `SwipeUp` is towards the left and `SwipeDown` towards the right
+    SwipeUp,
+    SwipeDown,
+    ScrollUp,
+    ScrollDown,
+}
+
+/// What happens when a [`Gesture`] is recognized
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum GestureAction {
+    /// The gesture is ignored and the event is propagated
+    #[default]
+    None,
+    /// Go to the next mode with a widget (Minimal -> Compact -> Expanded -> Overlay)
+    NextMode,
+    /// Go to the previous mode with a widget
+    PreviousMode,
+    /// Go to this mode, if it has a widget
+    Mode(ActivityMode),
+    /// Emit `gesture-action` with this name
+    Signal(String),
+}
+
+impl GestureAction {
+    fn changes_mode(&self) -> bool {
+        matches!(self, Self::NextMode | Self::PreviousMode | Self::Mode(_))
+    }
+}
+
+#[derive(Default)]
+pub(super) struct GestureActions {
+    pub(super) actions: HashMap<Gesture, GestureAction>,
+    pending_click: Option<glib::SourceId>,
+    press_point: (f64, f64),
+    long_pressed: bool,
+    /// A swipe or the drag-stretch threshold already changed the mode on this release
+    release_changed_mode: bool,
+}
+
+impl GestureActions {
+    pub(super) fn get(&self, gesture: Gesture) -> GestureAction {
+        self.actions.get(&gesture).cloned().unwrap_or_default()
+    }
+
+    fn press(&mut self, point: (f64, f64)) {
+        self.press_point = point;
+        self.long_pressed = false;
+        self.release_changed_mode = false;
+    }
+
+    /// Returns `false` if the mode was already changed since the last press,
+    /// the swipe and drag-stretch controllers both see the same release
+    fn claim_mode_change(&mut self) -> bool {
+        !std::mem::replace(&mut self.release_changed_mode, true)
+    }
+}
+
+/// Add the click, long press, swipe and scroll controllers
+///
+/// They never claim the events used by the drag-stretch gesture:
+/// a click is ignored if the pointer moved further than the drag threshold
+/// and a swipe needs to be faster than [`SWIPE_MIN_VELOCITY`]
+pub(super) fn add_controllers(obj: &ActivityWidget) {
+    let click = gtk::GestureClick::builder()
+        .button(gdk::BUTTON_PRIMARY)
+        .name("click-gesture")
+        .build();
+    click.connect_pressed(|gest, n_press, x, y| {
+        let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
+        if n_press == 1 {
+            obj.imp().gestures.borrow_mut().press((x, y));
+        } else if n_press == 2 {
+            cancel_pending_click(&obj);
+            run(&obj, Gesture::DoubleClick);
+        }
+    });
+    click.connect_released(|gest, n_press, x, y| {
+        let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
+        let gestures = obj.imp().gestures.borrow();
+        let (start_x, start_y) = gestures.press_point;
+        let long_pressed = gestures.long_pressed;
+        let has_double_click = gestures.get(Gesture::DoubleClick) != GestureAction::None;
+        drop(gestures);
+        if n_press != 1 && has_double_click {
+            // the double click was handled on press
+            return;
+        }
+
+        let threshold = gtk::Settings::default()
+            .map(|settings| settings.gtk_dnd_drag_threshold())
+            .unwrap_or(8) as f64;
+        if long_pressed || (x - start_x).hypot(y - start_y) > threshold {
+            // it was a drag or a long press
+            return;
+        }
+        if !has_double_click {
+            run(&obj, Gesture::Click);
+            return;
+        }
+        // wait to know if it's a double click
+        let double_click_time = gtk::Settings::default()
+            .map(|settings| settings.gtk_double_click_time())
+            .unwrap_or(400) as u64;
+        let weak = obj.downgrade();
+        let source =
+            glib::timeout_add_local_once(Duration::from_millis(double_click_time), move || {
+                let Some(obj) = weak.upgrade() else {
+                    return;
+                };
+                obj.imp().gestures.borrow_mut().pending_click = None;
+                run(&obj, Gesture::Click);
+            });
+        cancel_pending_click(&obj);
+        obj.imp().gestures.borrow_mut().pending_click = Some(source);
+    });
+    obj.add_controller(click);
+
+    let long_press = gtk::GestureLongPress::builder()
+        .button(gdk::BUTTON_PRIMARY)
+        .name("long-press-gesture")
+        .build();
+    long_press.connect_pressed(|gest, _, _| {
+        let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
+        if run(&obj, Gesture::LongPress) {
+            obj.imp().gestures.borrow_mut().long_pressed = true;
+        }
+    });
+    obj.add_controller(long_press);
+
+    let swipe = gtk::GestureSwipe::builder()
+        .button(gdk::BUTTON_PRIMARY)
+        .name("swipe-gesture")
+        .build();
+    swipe.connect_swipe(|gest, velocity_x, velocity_y| {
+        let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
+        // (short axis, long axis) of the island
+        let (velocity, velocity_long) = match obj.orientation() {
+            gtk::Orientation::Vertical => (velocity_x, velocity_y),
+            _ => (velocity_y, velocity_x),
+        };
+        if velocity.abs() < SWIPE_MIN_VELOCITY || velocity.abs() < velocity_long.abs() {
+            return;
+        }
+        let gesture = if velocity < 0.0 {
+            Gesture::SwipeUp
+        } else {
+            Gesture::SwipeDown
+        };
+        if obj.imp().gestures.borrow().get(gesture).changes_mode() && !claim_mode_change(&obj) {
+            return;
+        }
+        run(&obj, gesture);
+    });
+    obj.add_controller(swipe);
+
+    let scroll = gtk::EventControllerScroll::new(
+        EventControllerScrollFlags::VERTICAL | EventControllerScrollFlags::DISCRETE,
+    );
+    scroll.set_name(Some("scroll-controller"));
+    scroll.connect_scroll(|ctrl, _, dy| {
+        let obj = ctrl.widget().downcast::<ActivityWidget>().unwrap();
+        let gesture = if dy < 0.0 {
+            Gesture::ScrollUp
+        } else if dy > 0.0 {
+            Gesture::ScrollDown
+        } else {
+            return glib::Propagation::Proceed;
+        };
+        if run(&obj, gesture) {
+            glib::Propagation::Stop
+        } else {
+            glib::Propagation::Proceed
+        }
+    });
+    obj.add_controller(scroll);
+}
+
+/// Used by the swipe and the drag-stretch threshold so that a single release changes the mode only once
+pub(super) fn claim_mode_change(obj: &ActivityWidget) -> bool {
+    obj.imp().gestures.borrow_mut().claim_mode_change()
+}
+
+pub(super) fn cancel_pending_click(obj: &ActivityWidget) {
+    if let Some(source) = obj.imp().gestures.borrow_mut().pending_click.take() {
+        source.remove();
+    }
+}
+
+/// Run the action mapped to `gesture`
+///
+/// returns `false` if no action is mapped
+fn run(obj: &ActivityWidget, gesture: Gesture) -> bool {
+    let action = obj.imp().gestures.borrow().get(gesture);
+    match action {
+        GestureAction::None => return false,
+        GestureAction::NextMode => {
+            if let Some(mode) = adjacent_mode(obj, true) {
+                obj.set_mode(mode);
+            }
+        }
+        GestureAction::PreviousMode => {
+            if let Some(mode) = adjacent_mode(obj, false) {
+                obj.set_mode(mode);
+            }
+        }
+        GestureAction::Mode(mode) => {
+            if obj.get_widget_for_mode(mode).is_some() {
+                obj.set_mode(mode);
+            }
+        }
+        GestureAction::Signal(name) => {
+            obj.emit_by_name::<()>("gesture-action", &[&name]);
+        }
+    }
+    true
+}
+
+/// The closest mode with a widget after (or before) the current one, it doesn't wrap around
+pub(super) fn adjacent_mode(obj: &ActivityWidget, forward: bool) -> Option<ActivityMode> {
+    let current = obj.mode() as usize;
+    let has_widget = |mode: &ActivityMode| obj.get_widget_for_mode(*mode).is_some();
+    if forward {
+        ActivityMode::ALL[current + 1..]
+            .iter()
+            .find(|mode| has_widget(mode))
+            .copied()
+    } else {
+        ActivityMode::ALL[..current]
+            .iter()
+            .rev()
+            .find(|mode| has_widget(mode))
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_changes_the_mode_once() {
+        let mut gestures = GestureActions::default();
+        gestures.press((0.0, 0.0));
+        // the drag-stretch threshold changed the mode, the swipe is ignored
+        assert!(gestures.claim_mode_change());
+        assert!(!gestures.claim_mode_change());
+
+        gestures.press((0.0, 0.0));
+        assert!(gestures.claim_mode_change());
+    }
+
+    #[test]
+    fn only_mode_actions_change_the_mode() {
+        assert!(GestureAction::NextMode.changes_mode());
+        assert!(GestureAction::Mode(ActivityMode::Expanded).changes_mode());
+        assert!(!GestureAction::Signal("swipe".to_string()).changes_mode());
+        assert!(!GestureAction::None.changes_mode());
+    }
+}
@@ -1,14 +1,23 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, marker::PhantomData, sync::OnceLock};
 
 use abi::{gdk, glib, glib_macros, gtk, log};
-use glib::prelude::*;
+use glib::{prelude::*, subclass::Signal};
 use glib_macros::Properties;
 use gtk::{prelude::*, subclass::prelude::*, StateFlags};
 use rand::{distributions::Alphanumeric, Rng};
 
 use super::{
-    boxed_activity_mode::ActivityMode, local_css_context::ActivityWidgetLocalCssContext, util,
-    ActivityWidget,
+    animation::{self, VisualsAnimation},
+    auto_collapse::{self, AutoCollapse},
+    boxed_activity_mode::ActivityMode,
+    custom_mode::{ModeSlot, SizeRule},
+    drag_stretch::{self, DragStretch},
+    gesture::{self, GestureActions},
+    local_css_context::ActivityWidgetLocalCssContext,
+    shape::{self, IslandShape, SquircleMask},
+    transition::{self, Request, TransitionTracker},
+    transition_style::{self, TransitionStyle, TransitionStyles},
+    util, ActivityWidget,
 };
 
 #[derive(Properties)]
@@ -17,6 +26,12 @@ pub struct ActivityWidgetPriv {
     #[property(get, set, nick = "Change mode", blurb = "The Activity Mode")]
     pub(super) mode: RefCell<ActivityMode>,
 
+    #[property(name = "mode-name", get = Self::mode_name, set = Self::set_mode_name, nick = "Mode name")]
+    _mode_name: PhantomData<String>,
+
+    /// The mode set before the widget for it was added, applied when a GtkBuilder finishes building the widget
+    pub(super) pending_mode: RefCell<Option<ActivityMode>>,
+
     // #[property(get, nick = "Local CSS Context")]
     pub(super) local_css_context: RefCell<ActivityWidgetLocalCssContext>,
 
@@ -39,20 +54,71 @@ pub struct ActivityWidgetPriv {
     #[property(get, set, nick = "Enable stretching on drag")]
     pub(super) config_enable_drag_stretch: RefCell<bool>,
 
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// how much the widget resists stretching, it can grow at most by its size divided by this, 0 is linear
+    #[property(get, set, nick = "Drag stretch stiffness")]
+    pub(super) config_drag_stretch_stiffness: RefCell<f64>,
+
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// pixels to drag to go to the next or previous mode on release, 0 disables it
+    #[property(get, set, nick = "Drag stretch mode change threshold")]
+    pub(super) config_drag_stretch_threshold: RefCell<f64>,
+
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// milliseconds without interactions before the expanded and overlay modes collapse, 0 disables it
+    #[property(get, set, nick = "Auto collapse timeout")]
+    pub(super) config_auto_collapse_timeout: RefCell<u64>,
+
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// use the old css transitions instead of drawing the mode widgets in snapshot
+    #[property(get, set, nick = "Use css transitions")]
+    pub(super) config_css_transitions: RefCell<bool>,
+
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// the transition styles of the mode pairs in ron
+    #[property(get, set, nick = "Transition styles")]
+    pub(super) config_transition_styles: RefCell<String>,
+
+    /// To be used by dynisland::app and layout managers only
+    ///
+    /// the shape of the island in ron, empty to use the css border-radius
+    #[property(get, set, nick = "Island shape")]
+    pub(super) config_island_shape: RefCell<String>,
+
+    /// The direction of the island, `Vertical` swaps the axes of the minimal size
+    #[property(get, set, nick = "Orientation", builder(gtk::Orientation::Horizontal))]
+    pub(super) orientation: RefCell<gtk::Orientation>,
+
     #[property(get, nick = "The Last Activity mode")]
     pub(super) last_mode: RefCell<ActivityMode>,
 
-    // pub(super) transition_manager: RefCell<TransitionManager>,
+    pub(super) transition: RefCell<TransitionTracker>,
+    pub(super) transition_styles: RefCell<TransitionStyles>,
+    pub(super) auto_collapse: RefCell<AutoCollapse>,
+    pub(super) gestures: RefCell<GestureActions>,
+    pub(super) drag_stretch: RefCell<DragStretch>,
+    pub(super) visuals: RefCell<VisualsAnimation>,
+    pub(super) island_shape: RefCell<IslandShape>,
+    pub(super) squircle_mask: RefCell<SquircleMask>,
+    /// The built-in modes (slots 0-3) followed by the custom modes
+    pub(super) slots: RefCell<Vec<ModeSlot>>,
+    /// The index in `slots` of the shown mode, `mode` is its base mode
+    pub(super) slot: RefCell<usize>,
     pub(super) background_widget: RefCell<Option<gtk::Widget>>,
 
-    #[property(get, set, nick = "Minimal Mode Widget")]
-    pub(super) minimal_mode_widget: RefCell<Option<gtk::Widget>>,
-    #[property(get, set, nick = "Compact Mode Widget")]
-    pub(super) compact_mode_widget: RefCell<Option<gtk::Widget>>,
-    #[property(get, set, nick = "Expanded Mode Widget")]
-    pub(super) expanded_mode_widget: RefCell<Option<gtk::Widget>>,
-    #[property(get, set, nick = "Overlay Mode Widget")]
-    pub(super) overlay_mode_widget: RefCell<Option<gtk::Widget>>,
+    #[property(name = "minimal-mode-widget", get = Self::minimal_mode_widget, set = Self::set_minimal_mode_widget, nick = "Minimal Mode Widget")]
+    _minimal_mode_widget: PhantomData<Option<gtk::Widget>>,
+    #[property(name = "compact-mode-widget", get = Self::compact_mode_widget, set = Self::set_compact_mode_widget, nick = "Compact Mode Widget")]
+    _compact_mode_widget: PhantomData<Option<gtk::Widget>>,
+    #[property(name = "expanded-mode-widget", get = Self::expanded_mode_widget, set = Self::set_expanded_mode_widget, nick = "Expanded Mode Widget")]
+    _expanded_mode_widget: PhantomData<Option<gtk::Widget>>,
+    #[property(name = "overlay-mode-widget", get = Self::overlay_mode_widget, set = Self::set_overlay_mode_widget, nick = "Overlay Mode Widget")]
+    _overlay_mode_widget: PhantomData<Option<gtk::Widget>>,
 }
 
 //default data
@@ -75,24 +141,75 @@ impl Default for ActivityWidgetPriv {
         let enable_stretch = false;
         Self {
             mode: RefCell::new(ActivityMode::Minimal),
+            _mode_name: PhantomData,
+            pending_mode: RefCell::new(None),
             local_css_context: RefCell::new(css_ctx),
             config_minimal_height: RefCell::new(min_h),
             config_minimal_width: RefCell::new(min_w),
             config_blur_radius: RefCell::new(blur),
             config_enable_drag_stretch: RefCell::new(enable_stretch),
+            config_drag_stretch_stiffness: RefCell::new(1.0),
+            config_drag_stretch_threshold: RefCell::new(0.0),
+            config_auto_collapse_timeout: RefCell::new(0),
+            config_css_transitions: RefCell::new(false),
+            config_transition_styles: RefCell::new(String::new()),
+            config_island_shape: RefCell::new(String::new()),
             last_mode: RefCell::new(ActivityMode::Minimal),
+            orientation: RefCell::new(gtk::Orientation::Horizontal),
             name: RefCell::new(name),
-            minimal_mode_widget: RefCell::new(None),
-            compact_mode_widget: RefCell::new(None),
-            expanded_mode_widget: RefCell::new(None),
-            overlay_mode_widget: RefCell::new(None),
+            _minimal_mode_widget: PhantomData,
+            _compact_mode_widget: PhantomData,
+            _expanded_mode_widget: PhantomData,
+            _overlay_mode_widget: PhantomData,
             background_widget: RefCell::new(None),
+            transition: RefCell::new(TransitionTracker::default()),
+            transition_styles: RefCell::new(TransitionStyles::default()),
+            auto_collapse: RefCell::new(AutoCollapse::default()),
+            gestures: RefCell::new(GestureActions::default()),
+            drag_stretch: RefCell::new(DragStretch::default()),
+            visuals: RefCell::new(VisualsAnimation::default()),
+            island_shape: RefCell::new(IslandShape::default()),
+            squircle_mask: RefCell::new(SquircleMask::default()),
+            slots: RefCell::new(ActivityMode::ALL.map(ModeSlot::builtin).into()),
+            slot: RefCell::new(ActivityMode::Minimal as usize),
         }
     }
 }
 
 #[glib::derived_properties]
 impl ObjectImpl for ActivityWidgetPriv {
+    fn signals() -> &'static [Signal] {
+        static SIGNALS: OnceLock<Vec<Signal>> = OnceLock::new();
+        SIGNALS.get_or_init(|| {
+            vec![
+                Signal::builder("mode-changed")
+                    .param_types([ActivityMode::static_type(), ActivityMode::static_type()])
+                    .build(),
+                Signal::builder("transition-started")
+                    .param_types([ActivityMode::static_type()])
+                    .build(),
+                Signal::builder("transition-finished")
+                    .param_types([ActivityMode::static_type()])
+                    .build(),
+                Signal::builder("drag-stretch-begin")
+                    .param_types([f64::static_type(), f64::static_type()])
+                    .build(),
+                Signal::builder("drag-stretch-update")
+                    .param_types([f64::static_type(), f64::static_type()])
+                    .build(),
+                Signal::builder("drag-stretch-end")
+                    .param_types([f64::static_type(), f64::static_type()])
+                    .build(),
+                Signal::builder("gesture-action")
+                    .param_types([String::static_type()])
+                    .build(),
+                Signal::builder("mode-name-changed")
+                    .param_types([String::static_type(), String::static_type()])
+                    .build(),
+            ]
+        })
+    }
+
     fn constructed(&self) {
         self.parent_constructed();
         let background = gtk::Box::builder()
@@ -110,6 +227,8 @@ impl ObjectImpl for ActivityWidgetPriv {
         );
 
         self.add_drag_controller();
+        gesture::add_controllers(&self.obj());
+        auto_collapse::connect_interactions(&self.obj());
 
         background.set_parent(&*self.obj());
         self.background_widget
@@ -121,95 +240,16 @@ impl ObjectImpl for ActivityWidgetPriv {
             "mode" => {
                 // Replace old values if the mode is valid
                 let mode = value.get().unwrap();
-                let obj = self.obj();
-
-                if self.get_mode_widget(mode).borrow().is_none() {
+                if self.slot_widget(mode as usize).is_none() {
+                    self.pending_mode.replace(Some(mode));
                     return;
                 }
-                if let Some(prev) = self
-                    .get_mode_widget(*self.last_mode.borrow())
-                    .borrow()
-                    .as_ref()
-                {
-                    prev.remove_css_class("prev");
-                    prev.remove_css_class("next");
-                }
-                match mode {
-                    ActivityMode::Minimal => {
-                        obj.add_css_class("in-minimal-mode");
-                        obj.remove_css_class("in-compact-mode");
-                        obj.remove_css_class("in-expanded-mode");
-                        obj.remove_css_class("in-overlay-mode");
-                    }
-                    ActivityMode::Compact => {
-                        obj.remove_css_class("in-minimal-mode");
-                        obj.add_css_class("in-compact-mode");
-                        obj.remove_css_class("in-expanded-mode");
-                        obj.remove_css_class("in-overlay-mode");
-                    }
-                    ActivityMode::Expanded => {
-                        obj.remove_css_class("in-minimal-mode");
-                        obj.remove_css_class("in-compact-mode");
-                        obj.add_css_class("in-expanded-mode");
-                        obj.remove_css_class("in-overlay-mode");
-                    }
-                    ActivityMode::Overlay => {
-                        obj.remove_css_class("in-minimal-mode");
-                        obj.remove_css_class("in-compact-mode");
-                        obj.remove_css_class("in-expanded-mode");
-                        obj.add_css_class("in-overlay-mode");
-                    }
-                }
-
-                self.last_mode.replace(*self.mode.borrow());
-                self.mode.replace(mode);
-
-                // let last_mode = *self.last_mode.borrow();
-
-                let mut css_context = self.local_css_context.borrow_mut();
-                let min_height = *self.config_minimal_height.borrow();
-                let min_width = *self.config_minimal_width.borrow();
-
-                let next_size =
-                    Self::get_final_widget_size_for_mode(&obj, mode, min_height, min_width);
-                // log::debug!("next_size: {:?}", next_size);
-                // let prev_size=self.get_final_allocation_for_mode(last_mode, min_height);
-
-                // TODO add css classes {active, bigger, smaller, last...} to the widgets accordingly
-                // let bigger = next_size.0 > prev_size.0 || next_size.1 > prev_size.1;
-
-                // Set properties to start the css transition
-
-                css_context.set_opacity_all(util::get_property_slice_for_mode_f64(mode, 1.0, 0.0));
-
-                let blur_radius = *self.config_blur_radius.borrow();
-                css_context.set_blur_all(util::get_property_slice_for_mode_f64(
-                    mode,
-                    0.0,
-                    blur_radius,
-                ));
-
-                let stretches = Self::get_stretches(&obj, next_size, min_height, min_width);
-                log::trace!("stretches: {:?}", stretches);
-                css_context.set_stretch_all(stretches, None);
-
-                if let Some(next) = self.get_mode_widget(mode).borrow().as_ref() {
-                    next.remove_css_class("prev");
-                    next.add_css_class("next");
-                    next.set_visible(true);
-                    //put at the end so it receives the inputs
-                    next.insert_before(self.obj().as_ref(), Option::None::<&gtk::Widget>);
-                    css_context.set_size((next_size.0 as i32, next_size.1 as i32));
-                };
-                if let Some(prev) = self
-                    .get_mode_widget(*self.last_mode.borrow())
-                    .borrow()
-                    .as_ref()
-                {
-                    prev.remove_css_class("next");
-                    prev.add_css_class("prev");
-                }
-                self.obj().queue_draw(); // Queue a draw call with the updated value
+                self.pending_mode.replace(None);
+                // the transition policy can postpone the change until the running transition finishes
+                self.request_slot(mode as usize);
+            }
+            "mode-name" => {
+                self.set_mode_name(value.get().unwrap());
             }
             "name" => {
                 self.obj().remove_css_class(&self.name.borrow());
@@ -227,6 +267,20 @@ impl ObjectImpl for ActivityWidgetPriv {
                     .borrow_mut()
                     .set_config_minimal_height(value.get().unwrap());
             }
+            "orientation" => {
+                let orientation: gtk::Orientation = value.get().unwrap();
+                self.orientation.replace(orientation);
+                self.local_css_context
+                    .borrow_mut()
+                    .set_orientation(orientation);
+                if orientation == gtk::Orientation::Vertical {
+                    self.obj().add_css_class("vertical");
+                } else {
+                    self.obj().remove_css_class("vertical");
+                }
+                self.refresh_mode(); //the forced axes changed
+                self.obj().queue_resize();
+            }
             "config-minimal-width" => {
                 let width = value.get().unwrap();
                 self.config_minimal_width.replace(width);
@@ -238,68 +292,68 @@ impl ObjectImpl for ActivityWidgetPriv {
                 self.config_enable_drag_stretch
                     .replace(value.get().unwrap());
             }
-            "minimal-mode-widget" => {
-                let widget: Option<gtk::Widget> = value.get().unwrap();
-                if let Some(content) = &*self.minimal_mode_widget.borrow() {
-                    content.unparent();
-                    content.remove_css_class("mode-minimal");
+            "config-drag-stretch-stiffness" => {
+                self.config_drag_stretch_stiffness
+                    .replace(value.get().unwrap());
+            }
+            "config-drag-stretch-threshold" => {
+                self.config_drag_stretch_threshold
+                    .replace(value.get().unwrap());
+            }
+            "config-css-transitions" => {
+                let enabled = value.get().unwrap();
+                self.config_css_transitions.replace(enabled);
+                self.local_css_context
+                    .borrow_mut()
+                    .set_css_transitions(enabled);
+                animation::reset(&self.obj());
+                // the animation that would have finished it was stopped
+                transition::animation_ended(&self.obj());
+                self.obj().queue_resize();
+            }
+            "config-transition-styles" => {
+                let config: String = value.get().unwrap();
+                match transition_style::parse_styles(&config) {
+                    Ok(pairs) => {
+                        self.transition_styles.borrow_mut().pairs = pairs;
+                        self.config_transition_styles.replace(config);
+                    }
+                    Err(err) => log::warn!("invalid transition styles: {err}"),
                 }
-                self.minimal_mode_widget.replace(widget);
-                if let Some(widget) = self.minimal_mode_widget.borrow().as_ref() {
-                    widget.set_parent(self.obj().upcast_ref::<gtk::Widget>());
-                    widget.add_css_class("mode-minimal");
-                    widget.set_overflow(gtk::Overflow::Hidden);
+            }
+            "config-island-shape" => {
+                let config: String = value.get().unwrap();
+                match shape::parse_shape(&config) {
+                    Ok(shape) => {
+                        self.island_shape.replace(shape);
+                        self.config_island_shape.replace(config);
+                        self.local_css_context
+                            .borrow_mut()
+                            .set_clip_background(shape != IslandShape::Css);
+                        if animation::retarget(&self.obj(), true) {
+                            self.obj().queue_resize();
+                        }
+                        self.obj().queue_draw();
+                    }
+                    Err(err) => log::warn!("invalid island shape: {err}"),
                 }
-                self.obj().set_mode(self.obj().mode()); //update the size and the position of the widget
-                self.obj().queue_draw(); // Queue a draw call with the updated widget
+            }
+            "config-auto-collapse-timeout" => {
+                self.config_auto_collapse_timeout
+                    .replace(value.get().unwrap());
+                auto_collapse::restart(&self.obj());
+            }
+            "minimal-mode-widget" => {
+                self.set_mode_widget(ActivityMode::Minimal, value.get().unwrap());
             }
             "compact-mode-widget" => {
-                let widget: Option<gtk::Widget> = value.get().unwrap();
-                if let Some(content) = &*self.compact_mode_widget.borrow() {
-                    content.unparent();
-                    content.remove_css_class("mode-compact");
-                }
-                self.compact_mode_widget.replace(widget);
-                if let Some(widget) = self.compact_mode_widget.borrow().as_ref() {
-                    widget.set_parent(self.obj().upcast_ref::<gtk::Widget>());
-                    widget.add_css_class("mode-compact");
-                    widget.set_overflow(gtk::Overflow::Hidden);
-                }
-
-                self.obj().set_mode(self.obj().mode()); //update the size and the position of the widget
-                self.obj().queue_draw(); // Queue a draw call with the updated widget
+                self.set_mode_widget(ActivityMode::Compact, value.get().unwrap());
             }
             "expanded-mode-widget" => {
-                let widget: Option<gtk::Widget> = value.get().unwrap();
-                if let Some(content) = &*self.expanded_mode_widget.borrow() {
-                    content.unparent();
-                    content.remove_css_class("mode-expanded");
-                }
-                self.expanded_mode_widget.replace(widget);
-                if let Some(widget) = self.expanded_mode_widget.borrow().as_ref() {
-                    widget.set_parent(self.obj().upcast_ref::<gtk::Widget>());
-                    widget.add_css_class("mode-expanded");
-                    widget.set_overflow(gtk::Overflow::Hidden);
-                }
-
-                self.obj().set_mode(self.obj().mode()); //update the size and the position of the widget
-                self.obj().queue_draw(); // Queue a draw call with the updated widget
+                self.set_mode_widget(ActivityMode::Expanded, value.get().unwrap());
             }
             "overlay-mode-widget" => {
-                let widget: Option<gtk::Widget> = value.get().unwrap();
-                if let Some(content) = &*self.overlay_mode_widget.borrow() {
-                    content.unparent();
-                    content.remove_css_class("mode-overlay");
-                }
-                self.overlay_mode_widget.replace(widget);
-                if let Some(widget) = self.overlay_mode_widget.borrow().as_ref() {
-                    widget.set_parent(self.obj().upcast_ref::<gtk::Widget>());
-                    widget.add_css_class("mode-overlay");
-                    widget.set_overflow(gtk::Overflow::Hidden);
-                }
-
-                self.obj().set_mode(self.obj().mode()); //update the size and the position of the widget
-                self.obj().queue_draw(); // Queue a draw call with the updated widget
+                self.set_mode_widget(ActivityMode::Overlay, value.get().unwrap());
             }
 
             x => panic!("Tried to set inexistant property of ActivityWidget: {}", x),
@@ -308,141 +362,410 @@ impl ObjectImpl for ActivityWidgetPriv {
 
     fn dispose(&self) {
         // log::warn!("{} dispose", self.name.borrow());
+        auto_collapse::cancel(&self.obj());
+        gesture::cancel_pending_click(&self.obj());
+        drag_stretch::stop(&self.obj());
+        animation::reset(&self.obj());
         if let Some(widget) = self.background_widget.borrow_mut().take() {
             widget.unparent();
         }
-        if let Some(widget) = self.minimal_mode_widget.borrow_mut().take() {
+        for widget in self.all_mode_widgets() {
             widget.unparent();
         }
-        if let Some(widget) = self.compact_mode_widget.borrow_mut().take() {
-            widget.unparent();
-        }
-        if let Some(widget) = self.expanded_mode_widget.borrow_mut().take() {
-            widget.unparent();
+        self.slots.take();
+    }
+}
+
+impl WidgetImpl for ActivityWidgetPriv {
+    fn snapshot(&self, snapshot: &gtk::Snapshot) {
+        let obj = self.obj();
+        let shape = *self.island_shape.borrow();
+        let size = (obj.width() as f64, obj.height() as f64);
+        // the radius is only animated without css transitions
+        let radius =
+            animation::current_radius(&obj).unwrap_or_else(|| shape.radius(obj.mode(), size));
+        let clipped = shape::push_clip(
+            snapshot,
+            &shape,
+            size,
+            radius,
+            &mut self.squircle_mask.borrow_mut(),
+        );
+        if *self.config_css_transitions.borrow() {
+            self.parent_snapshot(snapshot);
+        } else {
+            animation::snapshot_children(&obj, snapshot);
         }
-        if let Some(widget) = self.overlay_mode_widget.borrow_mut().take() {
-            widget.unparent();
+        if clipped {
+            shape::pop_clip(snapshot);
         }
     }
 }
 
-impl WidgetImpl for ActivityWidgetPriv {}
+impl BuildableImpl for ActivityWidgetPriv {
+    fn add_child(&self, builder: &gtk::Builder, child: &glib::Object, type_: Option<&str>) {
+        let Some(type_) = type_ else {
+            // event controllers and other untyped children
+            self.parent_add_child(builder, child, type_);
+            return;
+        };
+        let Some(widget) = child.downcast_ref::<gtk::Widget>() else {
+            log::warn!(
+                "ActivityWidget: child of type {type_} is a {}, not a widget",
+                child.type_().name()
+            );
+            return;
+        };
+        let obj = self.obj();
+        match type_.parse::<ActivityMode>() {
+            Ok(mode) => obj.set_property(&format!("{mode}-mode-widget"), widget),
+            Err(_) => {
+                log::warn!("ActivityWidget: invalid child type {type_}, expected minimal, compact, expanded or overlay");
+            }
+        }
+    }
+
+    fn parser_finished(&self, builder: &gtk::Builder) {
+        self.parent_parser_finished(builder);
+        // the properties are set before the children are added
+        let pending_mode = self.pending_mode.borrow_mut().take();
+        if let Some(mode) = pending_mode {
+            self.obj().set_mode(mode);
+        }
+    }
+}
 
 impl ActivityWidgetPriv {
     fn add_drag_controller(&self) {
-        //TODO add configurable scaling factor / log function for stretching
         let drag_controller = gtk::GestureDrag::builder()
             .button(gdk::BUTTON_PRIMARY)
             .name("drag-gesture")
             .build();
-        drag_controller.connect_drag_begin(|gest, _, _| {
+        drag_controller.connect_drag_begin(|gest, x, y| {
             let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
             if !obj.config_enable_drag_stretch() {
                 return;
             }
-            obj.add_css_class("dragging");
+            drag_stretch::begin(&obj, x, y);
         });
         drag_controller.connect_drag_update(|gest, x, y| {
             let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
-            // log::info!("enable stretch: {}", obj.local_css_context().get_config_enable_drag_stretch());
             if !obj.config_enable_drag_stretch() {
                 return;
             }
-            let min_height = obj.config_minimal_height();
-            let min_width = obj.config_minimal_width();
-            let starting_size =
-                Self::get_final_widget_size_for_mode(&obj, obj.mode(), min_height, min_width);
-            let x = if gest.start_point().unwrap().0 < starting_size.0 / 2.0 {
-                -x
-            } else {
-                x
-            };
-            let y = if gest.start_point().unwrap().1 < starting_size.1 / 2.0 {
-                -y
-            } else {
-                y
-            };
-            // log::info!("{:?} {:?}",start, starting_size);
-            // log::info!("continue: {x} {y}");
-            let current_size =
-                Self::get_final_widget_size_for_mode(&obj, obj.mode(), min_height, min_width);
-            let max_screen_size = util::get_max_monitors_size();
-            // log::info!("max: {:?}", max_screen_size);
-            let next_size = (
-                (current_size.0 * (1.0 + (x / max_screen_size.0 as f64)))
-                    .max(obj.config_minimal_width() as f64),
-                (current_size.1 * (1.0 + (y / max_screen_size.1 as f64)))
-                    .max(obj.config_minimal_height() as f64),
-            );
-            let mut stretches = Self::get_stretches(&obj, next_size, min_height, min_width);
-            let current_stretch = (next_size.0 / starting_size.0, next_size.1 / starting_size.1);
-            stretches[obj.mode() as usize] = current_stretch;
-            // log::trace!("stretches: {:?}", stretches);
-            let mut css_context = obj.imp().local_css_context.borrow_mut();
-            let translates = Self::get_translates(&obj, next_size, stretches, min_height);
-            css_context.set_stretch_all(stretches, Some(translates));
-
-            css_context.set_size((next_size.0 as i32, next_size.1 as i32));
-            obj.queue_draw();
+            let (x, y) = Self::get_stretch_offset(&obj, gest, x, y);
+            drag_stretch::update(&obj, x, y);
         });
-        drag_controller.connect_drag_end(|gest, _, _| {
+        drag_controller.connect_drag_end(|gest, x, y| {
             let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
-            if !obj.config_enable_drag_stretch() {
-                return;
-            }
-            if obj.has_css_class("dragging") {
-                obj.remove_css_class("dragging");
-                let min_height = obj.config_minimal_height();
-                let min_width = obj.config_minimal_width();
-                let next_size =
-                    Self::get_final_widget_size_for_mode(&obj, obj.mode(), min_height, min_width);
-                let stretches = Self::get_stretches(&obj, next_size, min_height, min_width);
-                // log::trace!("stretches: {:?}", stretches);
-                let mut css_context = obj.imp().local_css_context.borrow_mut();
-                css_context.set_stretch_all(stretches, None);
-
-                css_context.set_size((next_size.0 as i32, next_size.1 as i32));
-                obj.queue_draw();
-            }
+            let (x, y) = Self::get_stretch_offset(&obj, gest, x, y);
+            drag_stretch::end(&obj, Some((x, y)));
+        });
+        drag_controller.connect_cancel(|gest, _| {
+            let obj = gest.widget().downcast::<ActivityWidget>().unwrap();
+            drag_stretch::end(&obj, None);
         });
         self.obj().connect_state_flags_changed(|obj, _| {
-            if !obj.config_enable_drag_stretch() {
+            if obj.state_flags().contains(StateFlags::ACTIVE) {
                 return;
             }
-            if obj.has_css_class("dragging") && !obj.state_flags().contains(StateFlags::ACTIVE) {
-                obj.remove_css_class("dragging");
-                let min_height = obj.config_minimal_height();
-                let min_width = obj.config_minimal_width();
-                let next_size =
-                    Self::get_final_widget_size_for_mode(obj, obj.mode(), min_height, min_width);
-                let stretches = Self::get_stretches(obj, next_size, min_height, min_width);
-                // log::trace!("stretches: {:?}", stretches);
-                let mut css_context = obj.imp().local_css_context.borrow_mut();
-                css_context.set_stretch_all(stretches, None);
-
-                css_context.set_size((next_size.0 as i32, next_size.1 as i32));
-                obj.queue_draw();
-            }
+            // the state can change before drag-end, a drag that's still running after it was cancelled
+            let obj = obj.downgrade();
+            glib::idle_add_local_once(move || {
+                let Some(obj) = obj.upgrade() else {
+                    return;
+                };
+                if !obj.state_flags().contains(StateFlags::ACTIVE) {
+                    // the offset isn't known
+                    drag_stretch::end(&obj, None);
+                }
+            });
         });
         self.obj().add_controller(drag_controller);
     }
-    pub(super) fn get_mode_widget(&self, mode: ActivityMode) -> &RefCell<Option<gtk::Widget>> {
-        match mode {
-            ActivityMode::Minimal => &self.minimal_mode_widget,
-            ActivityMode::Compact => &self.compact_mode_widget,
-            ActivityMode::Expanded => &self.expanded_mode_widget,
-            ActivityMode::Overlay => &self.overlay_mode_widget,
+
+    /// The drag offset, positive when dragging away from the center
+    fn get_stretch_offset(
+        obj: &ActivityWidget,
+        gest: &gtk::GestureDrag,
+        x: f64,
+        y: f64,
+    ) -> (f64, f64) {
+        let size =
+            Self::get_current_size(obj, obj.config_minimal_height(), obj.config_minimal_width());
+        let start = gest.start_point().unwrap_or_default();
+        let x = if start.0 < size.0 / 2.0 { -x } else { x };
+        let y = if start.1 < size.1 / 2.0 { -y } else { y };
+        (x, y)
+    }
+
+    /// Show the mode in `slot` now, or when the running transition finishes, according to the transition policy
+    pub(super) fn request_slot(&self, slot: usize) {
+        let name = self.slots.borrow()[slot].name.clone();
+        match transition::request(&self.obj(), &name) {
+            Request::Apply => self.apply_slot(slot),
+            Request::Refresh => self.relayout(),
+            Request::Wait => {}
         }
     }
 
-    pub(super) fn get_final_widget_size_for_mode(
+    /// Start the css transition to the mode in `slot`
+    pub(super) fn apply_slot(&self, slot: usize) {
+        if slot == self.current_slot() {
+            self.relayout();
+            return;
+        }
+        let obj = self.obj();
+        if drag_stretch::is_releasing(&obj) {
+            // the transition replaces the spring
+            drag_stretch::stop(&obj);
+        }
+        // only the widgets of the running transition have the prev and next classes
+        for widget in self.all_mode_widgets() {
+            widget.remove_css_class("prev");
+            widget.remove_css_class("next");
+        }
+
+        let old_mode = *self.mode.borrow();
+        let old_slot = self.current_slot();
+        let old_name = self.mode_name();
+        let mode = self.slots.borrow()[slot].base;
+        self.last_mode.replace(old_mode);
+        self.mode.replace(mode);
+        self.slot.replace(slot);
+        self.update_mode_classes();
+
+        // Set properties to start the css transition
+        let style = self.transition_styles.borrow().get(old_mode, mode);
+        self.update_css_context(&style);
+        self.show_current_widget();
+        if let Some(prev) = self.slot_widget(old_slot) {
+            prev.add_css_class("prev");
+        }
+        if animation::start_transition(&obj, old_slot, slot, &style) {
+            obj.queue_resize();
+        }
+        obj.queue_draw(); // Queue a draw call with the updated value
+        if old_mode != mode {
+            obj.emit_by_name::<()>("mode-changed", &[&old_mode, &mode]);
+        }
+        obj.emit_by_name::<()>("mode-name-changed", &[&old_name, &self.mode_name()]);
+        transition::start(&obj, mode);
+        auto_collapse::restart(&obj);
+    }
+
+    /// Update the size, the stretch and the visuals of the shown mode, after its widget or the config changed
+    ///
+    /// It doesn't change `last-mode` or the `prev` and `next` classes of a running transition
+    pub(super) fn relayout(&self) {
+        let obj = self.obj();
+        self.update_mode_classes();
+        // the style of the running (or last) transition
+        let style = self
+            .transition_styles
+            .borrow()
+            .get(*self.last_mode.borrow(), *self.mode.borrow());
+        self.update_css_context(&style);
+        self.show_current_widget();
+        if animation::retarget(&obj, true) {
+            obj.queue_resize();
+        }
+        obj.queue_draw();
+    }
+
+    /// Update the current mode after one of the mode widgets or the config changed
+    pub(super) fn refresh_mode(&self) {
+        if self.slot_widget(self.current_slot()).is_none() {
+            // there is nothing to show until the widget is added
+            return;
+        }
+        self.relayout();
+    }
+
+    /// Add the `in-<mode>-mode` classes of the shown mode to the ActivityWidget and remove the other ones
+    fn update_mode_classes(&self) {
+        let obj = self.obj();
+        let slot = self.current_slot();
+        let mode = *self.mode.borrow() as usize;
+        for (i, other) in self.slots.borrow().iter().enumerate() {
+            // a custom mode also has the class of its base mode
+            if i == slot || i == mode {
+                obj.add_css_class(&other.class());
+            } else {
+                obj.remove_css_class(&other.class());
+            }
+        }
+    }
+
+    /// Set the opacity, blur, stretch and size of the mode widgets in the local css context for the shown mode
+    fn update_css_context(&self, style: &TransitionStyle) {
+        let obj = self.obj();
+        let slot = self.current_slot();
+        let slot_count = self.slots.borrow().len();
+        let min_height = *self.config_minimal_height.borrow();
+        let min_width = *self.config_minimal_width.borrow();
+
+        let next_size = Self::get_current_size(&obj, min_height, min_width);
+        // log::debug!("next_size: {:?}", next_size);
+        let blur_radius = style
+            .blur
+            .unwrap_or_else(|| *self.config_blur_radius.borrow());
+        let stretches = if style.stretch {
+            Self::get_stretches(&obj, next_size, min_height, min_width)
+        } else {
+            vec![(1.0, 1.0); slot_count]
+        };
+        log::trace!("stretches: {:?}", stretches);
+
+        let mut css_context = self.local_css_context.borrow_mut();
+        css_context.set_opacity_all(&util::get_property_slice_for_slot_f64(
+            slot_count, slot, 1.0, 0.0,
+        ));
+        css_context.set_blur_all(&util::get_property_slice_for_slot_f64(
+            slot_count,
+            slot,
+            0.0,
+            blur_radius,
+        ));
+        css_context.set_stretch_all(&stretches, None);
+        if self.slot_widget(slot).is_some() {
+            css_context.set_size((next_size.0 as i32, next_size.1 as i32));
+        }
+    }
+
+    /// Give the `next` class to the widget of the shown mode and put it above the other ones
+    fn show_current_widget(&self) {
+        let Some(next) = self.slot_widget(self.current_slot()) else {
+            return;
+        };
+        next.add_css_class("next");
+        next.set_visible(true);
+        //put at the end so it receives the inputs
+        next.insert_before(self.obj().as_ref(), Option::None::<&gtk::Widget>);
+    }
+
+    pub(super) fn mode_name(&self) -> String {
+        self.slots.borrow()[self.current_slot()].name.clone()
+    }
+
+    fn set_mode_name(&self, name: String) {
+        match name.parse::<ActivityMode>() {
+            Ok(mode) => self.obj().set_mode(mode),
+            Err(err) => {
+                if let Err(custom_err) = self.obj().set_custom_mode(&name) {
+                    log::warn!("{err}, {custom_err}");
+                }
+            }
+        }
+    }
+
+    fn minimal_mode_widget(&self) -> Option<gtk::Widget> {
+        self.slot_widget(ActivityMode::Minimal as usize)
+    }
+
+    fn set_minimal_mode_widget(&self, widget: Option<gtk::Widget>) {
+        self.set_mode_widget(ActivityMode::Minimal, widget)
+    }
+
+    fn compact_mode_widget(&self) -> Option<gtk::Widget> {
+        self.slot_widget(ActivityMode::Compact as usize)
+    }
+
+    fn set_compact_mode_widget(&self, widget: Option<gtk::Widget>) {
+        self.set_mode_widget(ActivityMode::Compact, widget)
+    }
+
+    fn expanded_mode_widget(&self) -> Option<gtk::Widget> {
+        self.slot_widget(ActivityMode::Expanded as usize)
+    }
+
+    fn set_expanded_mode_widget(&self, widget: Option<gtk::Widget>) {
+        self.set_mode_widget(ActivityMode::Expanded, widget)
+    }
+
+    fn overlay_mode_widget(&self) -> Option<gtk::Widget> {
+        self.slot_widget(ActivityMode::Overlay as usize)
+    }
+
+    fn set_overlay_mode_widget(&self, widget: Option<gtk::Widget>) {
+        self.set_mode_widget(ActivityMode::Overlay, widget)
+    }
+
+    /// Replace the widget of a built-in mode
+    fn set_mode_widget(&self, mode: ActivityMode, widget: Option<gtk::Widget>) {
+        let (old, class) = {
+            let mut slots = self.slots.borrow_mut();
+            let slot = &mut slots[mode as usize];
+            (
+                std::mem::replace(&mut slot.widget, widget.clone()),
+                slot.widget_class(),
+            )
+        };
+        if let Some(content) = old {
+            content.unparent();
+            content.remove_css_class(&class);
+        }
+        if let Some(widget) = widget {
+            widget.set_parent(self.obj().upcast_ref::<gtk::Widget>());
+            widget.add_css_class(&class);
+            widget.set_overflow(gtk::Overflow::Hidden);
+        }
+        self.refresh_mode(); //update the size and the position of the widget
+        self.obj().queue_draw(); // Queue a draw call with the updated widget
+    }
+
+    /// The index of the shown mode: 0-3 for the built-in modes, then one for each custom mode
+    pub(super) fn current_slot(&self) -> usize {
+        *self.slot.borrow()
+    }
+
+    /// The slot of a custom mode
+    pub(super) fn custom_slot(&self, name: &str) -> Option<usize> {
+        self.slots
+            .borrow()
+            .iter()
+            .skip(ActivityMode::ALL.len())
+            .position(|custom| custom.name == name)
+            .map(|index| ActivityMode::ALL.len() + index)
+    }
+
+    pub(super) fn slot_widget(&self, slot: usize) -> Option<gtk::Widget> {
+        self.slots.borrow().get(slot)?.widget.clone()
+    }
+
+    /// The slot of a mode widget, `None` for the background
+    pub(super) fn slot_of_widget(&self, widget: &gtk::Widget) -> Option<usize> {
+        self.slots
+            .borrow()
+            .iter()
+            .position(|slot| slot.widget.as_ref() == Some(widget))
+    }
+
+    /// The widgets of the built-in and custom modes
+    pub(super) fn all_mode_widgets(&self) -> Vec<gtk::Widget> {
+        self.slots
+            .borrow()
+            .iter()
+            .filter_map(|slot| slot.widget.clone())
+            .collect()
+    }
+
+    /// The final size of the widget in `slot`, the current size if the slot doesn't have a widget
+    pub(super) fn get_final_widget_size_for_slot(
         obj: &ActivityWidget,
-        mode: ActivityMode,
+        slot: usize,
         min_height: i32,
         min_width: i32,
     ) -> (f64, f64) {
-        if let Some(widget) = &obj.get_widget_for_mode(mode) {
-            let tmp = util::get_final_widget_size(widget, obj.mode(), min_height, min_width);
+        let slot = obj.imp().slots.borrow()[slot].clone();
+        if let Some(widget) = &slot.widget {
+            let tmp = util::get_final_widget_size(
+                widget,
+                slot.size_rule,
+                obj.orientation(),
+                min_height,
+                min_width,
+            );
             (tmp.0 as f64, tmp.1 as f64)
         } else {
             (
@@ -453,193 +776,157 @@ impl ActivityWidgetPriv {
         }
     }
 
+    /// The final size of the shown mode widget, built-in or custom
+    pub(super) fn get_current_size(
+        obj: &ActivityWidget,
+        min_height: i32,
+        min_width: i32,
+    ) -> (f64, f64) {
+        Self::get_final_widget_size_for_slot(obj, obj.imp().current_slot(), min_height, min_width)
+    }
+
+    /// The stretch of every slot to fill `next_size`, the shown one isn't stretched
     pub(super) fn get_stretches(
         obj: &ActivityWidget,
         next_size: (f64, f64),
         min_height: i32,
         min_width: i32,
-    ) -> [(f64, f64); 4] {
-        let mut mode = ActivityMode::Minimal;
-        let min_stretch = if matches!(obj.mode(), ActivityMode::Minimal) {
-            (1.0, 1.0)
-        } else {
-            let min_alloc = if let Some(widget) = &obj.get_widget_for_mode(mode) {
-                let mut measure = util::get_child_aligned_allocation(
-                    (next_size.0 as i32, next_size.1 as i32, -1),
-                    widget,
-                    mode,
-                    min_height,
-                    false,
-                );
-                if measure.0 == 0 {
-                    measure.0 = next_size.0 as i32;
+    ) -> Vec<(f64, f64)> {
+        let current = obj.imp().current_slot();
+        let slots = obj.imp().slots.borrow().clone();
+        slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                if i == current {
+                    return (1.0, 1.0);
                 }
-                if measure.1 == 0 {
-                    measure.1 = next_size.1 as i32;
+                if let Some(widget) = &slot.widget {
+                    Self::get_stretch_for_widget(
+                        widget,
+                        slot.size_rule,
+                        obj.orientation(),
+                        next_size,
+                        min_height,
+                    )
+                } else {
+                    let alloc = Self::get_final_widget_size_for_slot(obj, i, min_height, min_width);
+                    // log::debug!("min get_size: {:?}, alloc: {:?}", min_alloc, min_alloc);
+                    (next_size.0 / alloc.0, next_size.1 / alloc.1)
                 }
-                (measure.0 as f64, measure.1 as f64)
-            } else {
-                Self::get_final_widget_size_for_mode(obj, mode, min_height, min_width)
-            };
-            // log::debug!("min get_size: {:?}, alloc: {:?}", min_alloc, min_alloc);
-            (next_size.0 / min_alloc.0, next_size.1 / min_alloc.1)
-        };
-
-        mode = ActivityMode::Compact;
-        let com_stretch = if matches!(obj.mode(), ActivityMode::Compact) {
-            (1.0, 1.0)
-        } else {
-            let com_alloc = if let Some(widget) = &obj.get_widget_for_mode(mode) {
-                let mut measure = util::get_child_aligned_allocation(
-                    (next_size.0 as i32, next_size.1 as i32, -1),
-                    widget,
-                    mode,
-                    min_height,
-                    false,
-                );
-                if measure.0 == 0 {
-                    measure.0 = next_size.0 as i32;
-                }
-                if measure.1 == 0 {
-                    measure.1 = next_size.1 as i32;
-                }
-                (measure.0 as f64, measure.1 as f64)
-            } else {
-                Self::get_final_widget_size_for_mode(obj, mode, min_height, min_width)
-            };
-            // log::debug!("min get_size: {:?}, alloc: {:?}", min_alloc, min_alloc);
-            (next_size.0 / com_alloc.0, next_size.1 / com_alloc.1)
-        };
+            })
+            .collect()
+    }
 
-        mode = ActivityMode::Expanded;
-        let exp_stretch = if matches!(obj.mode(), ActivityMode::Expanded) {
-            (1.0, 1.0)
-        } else {
-            let exp_alloc = if let Some(widget) = &obj.get_widget_for_mode(mode) {
-                let mut measure = util::get_child_aligned_allocation(
-                    (next_size.0 as i32, next_size.1 as i32, -1),
-                    widget,
-                    mode,
-                    min_height,
-                    false,
-                );
-                if measure.0 == 0 {
-                    measure.0 = next_size.0 as i32;
-                }
-                if measure.1 == 0 {
-                    measure.1 = next_size.1 as i32;
-                }
-                (measure.0 as f64, measure.1 as f64)
-            } else {
-                Self::get_final_widget_size_for_mode(obj, mode, min_height, min_width)
-            };
-            // log::debug!("min get_size: {:?}, alloc: {:?}", min_alloc, min_alloc);
-            (next_size.0 / exp_alloc.0, next_size.1 / exp_alloc.1)
-        };
+    /// The stretch needed by `widget` to fill `next_size`
+    fn get_stretch_for_widget(
+        widget: &gtk::Widget,
+        rule: SizeRule,
+        orientation: gtk::Orientation,
+        next_size: (f64, f64),
+        min_height: i32,
+    ) -> (f64, f64) {
+        let mut measure = util::get_child_aligned_allocation(
+            (next_size.0 as i32, next_size.1 as i32, -1),
+            widget,
+            rule,
+            orientation,
+            min_height,
+            false,
+        );
+        if measure.0 == 0 {
+            measure.0 = next_size.0 as i32;
+        }
+        if measure.1 == 0 {
+            measure.1 = next_size.1 as i32;
+        }
+        (
+            next_size.0 / measure.0 as f64,
+            next_size.1 / measure.1 as f64,
+        )
+    }
 
-        mode = ActivityMode::Overlay;
-        let ove_stretch = if matches!(obj.mode(), ActivityMode::Overlay) {
-            (1.0, 1.0)
-        } else {
-            let ove_alloc = if let Some(widget) = &obj.get_widget_for_mode(mode) {
-                let mut measure = util::get_child_aligned_allocation(
-                    (next_size.0 as i32, next_size.1 as i32, -1),
+    /// The translation of every slot that keeps its widget aligned while it's stretched
+    pub(super) fn get_translates(
+        obj: &ActivityWidget,
+        next_size: (f64, f64),
+        stretches: &[(f64, f64)],
+        min_height: i32,
+    ) -> Vec<(f64, f64)> {
+        let slots = obj.imp().slots.borrow().clone();
+        slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                let Some(widget) = &slot.widget else {
+                    return (0.0, 0.0);
+                };
+                let translate = Self::get_translate_for_widget(
                     widget,
-                    mode,
+                    slot.size_rule,
+                    obj.orientation(),
+                    next_size,
+                    stretches[i],
                     min_height,
-                    false,
                 );
-                if measure.0 == 0 {
-                    measure.0 = next_size.0 as i32;
+                if i == ActivityMode::Expanded as usize {
+                    (translate.0 / stretches[i].0, translate.1 / stretches[i].1)
+                } else {
+                    translate
                 }
-                if measure.1 == 0 {
-                    measure.1 = next_size.1 as i32;
-                }
-                (measure.0 as f64, measure.1 as f64)
-            } else {
-                Self::get_final_widget_size_for_mode(obj, mode, min_height, min_width)
-            };
-            // log::debug!("min get_size: {:?}, alloc: {:?}", min_alloc, min_alloc);
-            (next_size.0 / ove_alloc.0, next_size.1 / ove_alloc.1)
-        };
-
-        [
-            (min_stretch.0, min_stretch.1),
-            (com_stretch.0, com_stretch.1),
-            (exp_stretch.0, exp_stretch.1),
-            (ove_stretch.0, ove_stretch.1),
-        ]
+            })
+            .collect()
     }
 
-    pub(super) fn get_translates(
-        obj: &ActivityWidget,
+    /// The translation that keeps `widget` aligned while it's stretched to `next_size`
+    fn get_translate_for_widget(
+        widget: &gtk::Widget,
+        rule: SizeRule,
+        orientation: gtk::Orientation,
         next_size: (f64, f64),
-        stretches: [(f64, f64); 4],
+        stretch: (f64, f64),
         min_height: i32,
-    ) -> [(f64, f64); 4] {
-        let modes = [
-            ActivityMode::Minimal,
-            ActivityMode::Compact,
-            ActivityMode::Expanded,
-            ActivityMode::Overlay,
-        ];
-        let mut translates = [(0.0, 0.0); 4];
-        for (i, mode) in modes.into_iter().enumerate() {
-            let translate = if let Some(widget) = obj.get_widget_for_mode(mode) {
-                let measure = util::get_child_aligned_allocation(
-                    (next_size.0 as i32, next_size.1 as i32, -1),
-                    &widget,
-                    mode,
-                    min_height,
-                    false,
-                );
-                let widget_width = measure.0 as f64;
-                let widget_height = measure.1 as f64;
-                let x = match widget.halign() {
-                    gtk::Align::Start => (next_size.0 - widget_width) / 2.0,
-                    gtk::Align::End => -(next_size.0 - widget_width as f64) / 2.0,
-                    gtk::Align::Fill => {
-                        if widget_width > next_size.0 {
-                            0.0
-                        } else {
-                            -(next_size.0 - widget_width as f64 * stretches[i].0) / 2.0
-                        }
-                    }
-                    _ => {
-                        // center
-                        0.0
-                    }
-                };
-                let y = match widget.valign() {
-                    gtk::Align::Start => (next_size.1 - widget_height as f64) / 2.0,
-                    gtk::Align::End => -(next_size.1 - widget_height as f64) / 2.0,
-                    gtk::Align::Fill => {
-                        if widget_height > next_size.1 {
-                            0.0
-                        } else {
-                            -(next_size.1 - widget_height as f64 * stretches[i].1) / 2.0
-                        }
-                    }
-                    _ => {
-                        // center
-                        0.0
-                    }
-                };
-                (x, y)
-            } else {
-                (0.0, 0.0)
-            };
-            translates[i] = translate;
-            if mode == ActivityMode::Expanded {
-                translates[i] = (translate.0 / stretches[i].0, translate.1 / stretches[i].1);
-                // log::debug!(
-                //     "translate: {:?}, stretch: {:?}",
-                //     translates[i],
-                //     stretches[i]
-                // );
+    ) -> (f64, f64) {
+        let measure = util::get_child_aligned_allocation(
+            (next_size.0 as i32, next_size.1 as i32, -1),
+            widget,
+            rule,
+            orientation,
+            min_height,
+            false,
+        );
+        let widget_width = measure.0 as f64;
+        let widget_height = measure.1 as f64;
+        let x = match widget.halign() {
+            gtk::Align::Start => (next_size.0 - widget_width) / 2.0,
+            gtk::Align::End => -(next_size.0 - widget_width) / 2.0,
+            gtk::Align::Fill => {
+                if widget_width > next_size.0 {
+                    0.0
+                } else {
+                    -(next_size.0 - widget_width * stretch.0) / 2.0
+                }
             }
-        }
-
-        translates
+            _ => {
+                // center
+                0.0
+            }
+        };
+        let y = match widget.valign() {
+            gtk::Align::Start => (next_size.1 - widget_height) / 2.0,
+            gtk::Align::End => -(next_size.1 - widget_height) / 2.0,
+            gtk::Align::Fill => {
+                if widget_height > next_size.1 {
+                    0.0
+                } else {
+                    -(next_size.1 - widget_height * stretch.1) / 2.0
+                }
+            }
+            _ => {
+                // center
+                0.0
+            }
+        };
+        (x, y)
     }
 }
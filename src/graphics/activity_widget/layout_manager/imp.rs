@@ -3,9 +3,7 @@ use abi::{
     log,
 };
 
-use crate::graphics::activity_widget::{
-    boxed_activity_mode::ActivityMode, imp::ActivityWidgetPriv, util, ActivityWidget,
-};
+use crate::graphics::activity_widget::{animation, imp::ActivityWidgetPriv, util, ActivityWidget};
 
 #[derive(Default)]
 pub struct ActivityLayoutManagerPriv {}
@@ -28,18 +26,31 @@ impl LayoutManagerImpl for ActivityLayoutManagerPriv {
         let activity_widget = activity_widget.unwrap();
 
         let min_height = activity_widget.config_minimal_height();
-        if activity_widget.has_css_class("hidden") && orientation == gtk::Orientation::Horizontal {
+        // a hidden island collapses along its length
+        if activity_widget.has_css_class("hidden") && orientation == activity_widget.orientation() {
             return (0, 0, -1, -1);
         }
         if !activity_widget.has_css_class("dragging") {
-            let next_size = ActivityWidgetPriv::get_final_widget_size_for_mode(
+            let next_size = ActivityWidgetPriv::get_current_size(
                 activity_widget,
-                activity_widget.mode(),
                 min_height,
                 activity_widget.config_minimal_width(),
             );
             let mut css_context = activity_widget.imp().local_css_context.borrow_mut();
             css_context.set_size((next_size.0 as i32, next_size.1 as i32));
+            drop(css_context);
+            // retargeting can end the running transition, it can't be done during the layout
+            animation::queue_retarget(activity_widget);
+        }
+        if !activity_widget.config_css_transitions() {
+            // the size is animated on the frame clock instead of through the css of the background
+            if let Some(size) = animation::current_size(activity_widget) {
+                let size = match orientation {
+                    gtk::Orientation::Horizontal => size.0,
+                    _ => size.1,
+                } as i32;
+                return (min_height.max(size), min_height.max(size), -1, -1);
+            }
         }
         let first_child = activity_widget.first_child(); //should be the background widget
         match first_child {
@@ -65,49 +76,20 @@ impl LayoutManagerImpl for ActivityLayoutManagerPriv {
             content.allocate(width, height, -1, None);
         };
 
-        if let Some(content) = &*activity.minimal_mode_widget.borrow() {
-            let (width, height, transform) = util::get_child_aligned_allocation(
-                (width, height, baseline),
-                content,
-                ActivityMode::Minimal,
-                min_height,
-                activity_widget.has_css_class("dragging"),
-            );
-
-            content.allocate(width, height, -1, transform);
-        }
-        if let Some(content) = &*activity.compact_mode_widget.borrow() {
-            let (width, height, transform) = util::get_child_aligned_allocation(
-                (width, height, baseline),
-                content,
-                ActivityMode::Compact,
-                min_height,
-                activity_widget.has_css_class("dragging"),
-            );
-
-            content.allocate(width, height, -1, transform);
-        }
-        if let Some(content) = &*activity.expanded_mode_widget.borrow() {
+        for slot in activity.slots.borrow().iter() {
+            let Some(content) = &slot.widget else {
+                continue;
+            };
             let (width, height, transform) = util::get_child_aligned_allocation(
                 (width, height, baseline),
                 content,
-                ActivityMode::Expanded,
+                slot.size_rule,
+                activity_widget.orientation(),
                 min_height,
                 activity_widget.has_css_class("dragging"),
             );
 
             content.allocate(width, height, -1, transform);
         }
-        if let Some(content) = &*activity.overlay_mode_widget.borrow() {
-            let (width, height, transform) = util::get_child_aligned_allocation(
-                (width, height, baseline),
-                content,
-                ActivityMode::Overlay,
-                min_height,
-                activity_widget.has_css_class("dragging"),
-            );
-
-            content.allocate(width, height, -1, transform);
-        };
     }
 }
@@ -7,17 +7,46 @@ use rand::{distributions::Alphanumeric, Rng};
 
 use super::boxed_activity_mode::ActivityMode;
 
+/// The values of the widget of a mode, indexed by the slot of the mode in the ActivityWidget
+#[derive(Clone, Debug, PartialEq)]
+struct ModeStyle {
+    /// The name in the css class of the widget (`mode-<name>`)
+    name: String,
+    opacity: f64,
+    stretch: (f64, f64),
+    translate: (f64, f64),
+    blur: f64,
+}
+
+impl ModeStyle {
+    fn new(name: &str, opacity: f64, blur: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            opacity,
+            stretch: (1.0, 1.0),
+            translate: (0.0, 0.0),
+            blur,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ActivityWidgetLocalCssContext {
     css_provider: CssProvider,
     name: String,
 
     size: (i32, i32),
-    opacity: [f64; 4],
-    stretch: [(f64, f64); 4],
-    translate: Option<[(f64, f64); 4]>,
-    blur: [f64; 4],
+    /// The built-in modes followed by the custom modes
+    modes: Vec<ModeStyle>,
     stretch_on_resize: bool,
+    /// Generate the css for every change, otherwise the values are only stored
+    /// and the ActivityWidget draws them in its snapshot
+    css_transitions: bool,
+    /// The orientation of the island, a hidden island collapses along its long axis
+    orientation: gtk::Orientation,
+    /// The island has an [`IslandShape`](super::shape::IslandShape), the corners of `.activity-background`
+    /// are drawn by its clip so the css ones are removed
+    clip_background: bool,
 
     config_minimal_height: i32,
 }
@@ -29,11 +58,21 @@ impl ActivityWidgetLocalCssContext {
             css_provider: gtk::CssProvider::new(),
             name: name.to_string(),
             size: (40, 40),
-            opacity: [1.0, 0.0, 0.0, 0.0],
-            stretch: [(1.0, 1.0), (1.0, 1.0), (1.0, 1.0), (1.0, 1.0)],
-            translate: None,
-            blur: [0.0, 1.0, 1.0, 1.0],
+            modes: ActivityMode::ALL
+                .into_iter()
+                .map(|mode| {
+                    let shown = mode == ActivityMode::Minimal;
+                    ModeStyle::new(
+                        &mode.to_string(),
+                        if shown { 1.0 } else { 0.0 },
+                        if shown { 0.0 } else { 1.0 },
+                    )
+                })
+                .collect(),
             stretch_on_resize: true,
+            css_transitions: false,
+            orientation: gtk::Orientation::Horizontal,
+            clip_background: false,
             config_minimal_height: 40,
         }
     }
@@ -48,18 +87,34 @@ impl ActivityWidgetLocalCssContext {
     pub fn get_size(&self) -> (i32, i32) {
         self.size
     }
-    pub fn get_opacity(&self, mode: ActivityMode) -> f64 {
-        self.opacity[mode as usize]
+    /// The number of slots, the four built-in modes and the custom modes
+    pub fn get_slot_count(&self) -> usize {
+        self.modes.len()
+    }
+    pub fn get_opacity(&self, slot: usize) -> f64 {
+        self.modes[slot].opacity
     }
-    pub fn get_stretch(&self, mode: ActivityMode) -> (f64, f64) {
-        self.stretch[mode as usize]
+    pub fn get_stretch(&self, slot: usize) -> (f64, f64) {
+        self.modes[slot].stretch
     }
-    pub fn get_blur(&self, mode: ActivityMode) -> f64 {
-        self.blur[mode as usize]
+    pub fn get_translate(&self, slot: usize) -> (f64, f64) {
+        self.modes[slot].translate
+    }
+    pub fn get_blur(&self, slot: usize) -> f64 {
+        self.modes[slot].blur
     }
     pub fn get_stretch_on_resize(&self) -> bool {
         self.stretch_on_resize
     }
+    pub fn get_css_transitions(&self) -> bool {
+        self.css_transitions
+    }
+    pub fn get_orientation(&self) -> gtk::Orientation {
+        self.orientation
+    }
+    pub fn get_clip_background(&self) -> bool {
+        self.clip_background
+    }
     pub fn get_config_minimal_height(&self) -> i32 {
         self.config_minimal_height
     }
@@ -67,7 +122,7 @@ impl ActivityWidgetLocalCssContext {
     // SET
     pub fn set_name(&mut self, name: &str) {
         self.name = name.to_string();
-        self.update_provider()
+        self.reload_provider()
     }
     pub fn set_size(&mut self, size: (i32, i32)) {
         if self.size == size {
@@ -79,51 +134,86 @@ impl ActivityWidgetLocalCssContext {
         );
         self.update_provider()
     }
-    pub fn set_opacity(&mut self, mode: ActivityMode, opacity: f64) {
-        if self.opacity[mode as usize] == opacity {
+    /// Add the slot of a custom mode after the other ones, hidden
+    pub fn add_slot(&mut self, name: &str) {
+        self.modes.push(ModeStyle::new(name, 0.0, 0.0));
+        self.update_provider()
+    }
+    /// Remove the slot of a custom mode, the next slots move back by one
+    pub fn remove_slot(&mut self, slot: usize) {
+        self.modes.remove(slot);
+        self.update_provider()
+    }
+    pub fn set_opacity(&mut self, slot: usize, opacity: f64) {
+        if self.modes[slot].opacity == opacity {
             return;
         }
-        self.opacity[mode as usize] = opacity;
+        self.modes[slot].opacity = opacity;
         self.update_provider()
     }
-    pub fn set_opacity_all(&mut self, opacity: [f64; 4]) {
-        if self.opacity == opacity {
+    /// Set the opacity of every slot, `opacity` has one value for each slot
+    pub fn set_opacity_all(&mut self, opacity: &[f64]) {
+        if self
+            .modes
+            .iter()
+            .map(|mode| mode.opacity)
+            .eq(opacity.iter().copied())
+        {
             return;
         }
-        self.opacity = opacity;
+        for (mode, opacity) in self.modes.iter_mut().zip(opacity) {
+            mode.opacity = *opacity;
+        }
         self.update_provider()
     }
-    pub fn set_stretch(&mut self, mode: ActivityMode, stretch: (f64, f64)) {
-        if self.stretch[mode as usize] == stretch {
+    pub fn set_stretch(&mut self, slot: usize, stretch: (f64, f64)) {
+        if self.modes[slot].stretch == stretch {
             return;
         }
-        self.stretch[mode as usize] = stretch;
+        self.modes[slot].stretch = stretch;
         self.update_provider()
     }
-    pub fn set_stretch_all(
-        &mut self,
-        stretch: [(f64, f64); 4],
-        translate: Option<[(f64, f64); 4]>,
-    ) {
-        if self.stretch == stretch {
+    /// Set the stretch and the translation of every slot, no translation if `translate` is `None`
+    pub fn set_stretch_all(&mut self, stretch: &[(f64, f64)], translate: Option<&[(f64, f64)]>) {
+        let translate = |slot: usize| {
+            translate
+                .map(|translate| translate[slot])
+                .unwrap_or((0.0, 0.0))
+        };
+        if self
+            .modes
+            .iter()
+            .enumerate()
+            .all(|(slot, mode)| mode.stretch == stretch[slot] && mode.translate == translate(slot))
+        {
             return;
         }
-        self.translate = translate;
-        self.stretch = stretch;
+        for (slot, mode) in self.modes.iter_mut().enumerate() {
+            mode.stretch = stretch[slot];
+            mode.translate = translate(slot);
+        }
         self.update_provider()
     }
-    pub fn set_blur(&mut self, mode: ActivityMode, blur: f64) {
-        if self.blur[mode as usize] == blur {
+    pub fn set_blur(&mut self, slot: usize, blur: f64) {
+        if self.modes[slot].blur == blur {
             return;
         }
-        self.blur[mode as usize] = blur;
+        self.modes[slot].blur = blur;
         self.update_provider()
     }
-    pub fn set_blur_all(&mut self, blur: [f64; 4]) {
-        if self.blur == blur {
+    /// Set the blur of every slot, `blur` has one value for each slot
+    pub fn set_blur_all(&mut self, blur: &[f64]) {
+        if self
+            .modes
+            .iter()
+            .map(|mode| mode.blur)
+            .eq(blur.iter().copied())
+        {
             return;
         }
-        self.blur = blur;
+        for (mode, blur) in self.modes.iter_mut().zip(blur) {
+            mode.blur = *blur;
+        }
         self.update_provider()
     }
     pub fn set_stretch_on_resize(&mut self, stretch: bool) {
@@ -133,6 +223,28 @@ impl ActivityWidgetLocalCssContext {
         self.stretch_on_resize = stretch;
         self.update_provider()
     }
+    pub fn set_css_transitions(&mut self, enabled: bool) {
+        if self.css_transitions == enabled {
+            return;
+        }
+        self.css_transitions = enabled;
+        // remove the old rules if disabled
+        self.reload_provider()
+    }
+    pub fn set_clip_background(&mut self, clip: bool) {
+        if self.clip_background == clip {
+            return;
+        }
+        self.clip_background = clip;
+        self.reload_provider()
+    }
+    pub fn set_orientation(&mut self, orientation: gtk::Orientation) {
+        if self.orientation == orientation {
+            return;
+        }
+        self.orientation = orientation;
+        self.update_provider()
+    }
     pub fn set_config_minimal_height(&mut self, height: i32) {
         if self.config_minimal_height == height {
             return;
@@ -141,59 +253,43 @@ impl ActivityWidgetLocalCssContext {
         self.set_size(self.size);
     }
 
+    /// Load all the rules, without css transitions only the ones of `clip_background` are used
+    fn reload_provider(&self) {
+        if self.css_transitions {
+            self.update_provider()
+        } else {
+            self.css_provider.load_from_string(&self.clip_css());
+        }
+    }
+
+    fn clip_css(&self) -> String {
+        if !self.clip_background {
+            return String::new();
+        }
+        let name = self.name.as_str();
+        format!(
+            r".{name} .activity-background {{
+                    border-radius: 0px;
+                }}
+                "
+        )
+    }
+
     fn update_provider(&self) {
+        if !self.css_transitions {
+            return;
+        }
         let (w, h) = self.size;
         // let border_radius = self.border_radius;
         let name = self.name.as_str();
-        let (min_opacity, com_opacity, exp_opacity, ove_opacity) = (
-            self.opacity[0],
-            self.opacity[1],
-            self.opacity[2],
-            self.opacity[3],
-        );
-        let stretches = self.stretch.map(|(x, y)| {
-            let x = if !x.is_finite() { 1.0 } else { x };
-            let y = if !y.is_finite() { 1.0 } else { y };
-            (x, y)
-        });
-        let (min_stretch_x, com_stretch_x, exp_stretch_x, ove_stretch_x) = (
-            stretches[0].0,
-            stretches[1].0,
-            stretches[2].0,
-            stretches[3].0,
-        );
-        let (min_stretch_y, com_stretch_y, exp_stretch_y, ove_stretch_y) = (
-            stretches[0].1,
-            stretches[1].1,
-            stretches[2].1,
-            stretches[3].1,
-        );
-        let (min_translate_x, com_translate_x, exp_translate_x, ove_translate_x) =
-            if let Some(translate) = self.translate {
-                (
-                    translate[0].0,
-                    translate[1].0,
-                    translate[2].0,
-                    translate[3].0,
-                )
-            } else {
-                (0.0, 0.0, 0.0, 0.0)
-            };
-        let (min_translate_y, com_translate_y, exp_translate_y, ove_translate_y) =
-            if let Some(translate) = self.translate {
-                (
-                    translate[0].1,
-                    translate[1].1,
-                    translate[2].1,
-                    translate[3].1,
-                )
-            } else {
-                (0.0, 0.0, 0.0, 0.0)
-            };
-        let (min_blur, com_blur, exp_blur, ove_blur) =
-            (self.blur[0], self.blur[1], self.blur[2], self.blur[3]);
         // log::debug!("{size_timing_function}");
-        let css = if self.stretch_on_resize {
+        let long_axis = if self.orientation == gtk::Orientation::Vertical {
+            "min-height"
+        } else {
+            "min-width"
+        };
+        let mut css = self.clip_css();
+        css.push_str(&if self.stretch_on_resize {
             format!(
                 r".{name}:not(.hidden) .activity-background, .{name}:not(.hidden) .activity-background * {{ 
                     min-width: {w}px; 
@@ -201,29 +297,9 @@ impl ActivityWidgetLocalCssContext {
                 }}
 
                 .{name} .hidden .activity-background {{
-                    min-width: 0px;
-                }}
-                
-                .{name} .mode-minimal{{
-                    opacity: {min_opacity};
-                    transform: scale({min_stretch_x}, {min_stretch_y}) translate({min_translate_x}px, {min_translate_y}px);
-                    filter: blur({min_blur}px);
-                }}
-                .{name} .mode-compact{{
-                    opacity: {com_opacity};
-                    transform: scale({com_stretch_x}, {com_stretch_y}) translate({com_translate_x}px, {com_translate_y}px);
-                    filter: blur({com_blur}px);
+                    {long_axis}: 0px;
                 }}
-                .{name} .mode-expanded{{
-                    opacity: {exp_opacity};
-                    transform: scale({exp_stretch_x}, {exp_stretch_y}) translate({exp_translate_x}px, {exp_translate_y}px);
-                    filter: blur({exp_blur}px);
-                }}
-                .{name} .mode-overlay{{
-                    opacity: {ove_opacity};
-                    transform: scale({ove_stretch_x}, {ove_stretch_y}) translate({ove_translate_x}px, {ove_translate_y}px);
-                    filter: blur({ove_blur}px);
-                }}"
+                "
             )
         } else {
             format!(
@@ -231,29 +307,44 @@ impl ActivityWidgetLocalCssContext {
                     min-width: {w}px; 
                     min-height: {h}px;
                 }}
-
-                .{name} .mode-minimal{{
-                    opacity: {min_opacity};
-                    filter: blur({min_blur}px);
-                    transform: scale(1,1);
-                }}
-                .{name} .mode-compact{{
-                    opacity: {com_opacity};
-                    filter: blur({com_blur}px);
-                    transform: scale(1,1);
-                }}
-                .{name} .mode-expanded{{
-                    opacity: {exp_opacity};
-                    filter: blur({exp_blur}px);
-                    transform: scale(1,1);
-                }}
-                .{name} .mode-overlay{{
-                    opacity: {ove_opacity};
-                    filter: blur({ove_blur}px);
+                "
+            )
+        });
+        for mode in self.modes.iter() {
+            let mode_name = mode.name.as_str();
+            let opacity = mode.opacity;
+            let blur = mode.blur;
+            if self.stretch_on_resize {
+                let stretch_x = if mode.stretch.0.is_finite() {
+                    mode.stretch.0
+                } else {
+                    1.0
+                };
+                let stretch_y = if mode.stretch.1.is_finite() {
+                    mode.stretch.1
+                } else {
+                    1.0
+                };
+                let (translate_x, translate_y) = mode.translate;
+                css.push_str(&format!(
+                    r"
+                .{name} .mode-{mode_name}{{
+                    opacity: {opacity};
+                    transform: scale({stretch_x}, {stretch_y}) translate({translate_x}px, {translate_y}px);
+                    filter: blur({blur}px);
+                }}"
+                ));
+            } else {
+                css.push_str(&format!(
+                    r"
+                .{name} .mode-{mode_name}{{
+                    opacity: {opacity};
+                    filter: blur({blur}px);
                     transform: scale(1,1);
                 }}"
-            )
-        };
+                ));
+            }
+        }
         // log::debug!("{css}");
         self.css_provider.load_from_string(&css);
     }
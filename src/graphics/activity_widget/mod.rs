@@ -1,20 +1,43 @@
 // pub mod allocate_and_draw;
+mod animation;
+pub mod auto_collapse;
 pub mod boxed_activity_mode;
+pub mod builder;
+pub mod custom_mode;
+mod drag_stretch;
+pub mod gesture;
 pub mod imp;
 pub mod layout_manager;
 pub mod local_css_context;
 mod object_subclass_impl;
-use abi::{glib, gtk};
-use gtk::prelude::*;
+pub mod shape;
+pub mod transition;
+pub mod transition_style;
+use std::{collections::HashMap, time::Duration};
 
-use self::boxed_activity_mode::ActivityMode;
+use abi::{glib, gtk, log};
+use anyhow::{bail, Result};
+use glib::SignalHandlerId;
+use gtk::{prelude::*, subclass::prelude::*};
+
+use self::{
+    auto_collapse::AutoCollapseRule,
+    boxed_activity_mode::ActivityMode,
+    builder::ActivityWidgetBuilder,
+    custom_mode::{ModeSlot, SizeRule},
+    gesture::{Gesture, GestureAction},
+    shape::{self, IslandShape},
+    transition::TransitionPolicy,
+    transition_style::{self, TransitionStyle},
+};
 use super::util;
 
 glib::wrapper! {
     /// A Widget containing from 1 to 4 Widgets, one for each mode.
     /// It should contain at least the Minimal widget.
     ///
-    /// It also stretches on drag if enabled
+    /// It also stretches on drag if enabled, with a rubber-band resistance,
+    /// and springs back to its size when released
     ///
     /// The valign and halign properties of the mode widgets, along with the requested size,
     /// decide the size of the sub-widget during a mode change:
@@ -23,7 +46,14 @@ glib::wrapper! {
     /// # Properties
     ///
     /// * `mode` (get,set) - The current mode of the ActivityWidget
+    /// * `mode-name` (get,set) - The name of the current mode (example: "expanded" or the name of a custom mode),
+    ///   used to set the mode from GtkBuilder files
     /// * `last-mode` (get) - The last mode of the ActivityWidget
+    /// * `orientation` (get,set) - `Horizontal` (the default) for an island at the top or bottom of the screen,
+    ///   `Vertical` for one on a side. In a vertical island `config-minimal-height` is the forced width of
+    ///   the minimal and compact modes and `config-minimal-width` is the forced height of the minimal mode.
+    ///   The ActivityWidget has the `vertical` class. The swipe gestures and the slide effects move along the short axis
+    ///   and a hidden island collapses along the long axis
     ///
     /// * `minimal-mode-widget` (get,set) - The widget to be shown in minimal mode
     /// * `compact-mode-widget` (get,set) - The widget to be shown in compact mode
@@ -38,8 +68,63 @@ glib::wrapper! {
     /// * `config-minimal-width` (get,set) - The minimum width of the ActivityWidget
     /// * `config-blur-radius` (get,set) - The blur radius of the ActivityWidget during a transition
     /// * `config-enable-drag-stretch` (get,set) - Whether the ActivityWidget can be stretched by dragging
+    /// * `config-drag-stretch-stiffness` (get,set) - How much the ActivityWidget resists stretching,
+    ///   it can grow at most by its size divided by this value, 0 makes the stretch linear
+    /// * `config-drag-stretch-threshold` (get,set) - Drag offset in pixels that changes to the next (or previous) mode on release,
+    ///   0 disables it
+    /// * `config-css-transitions` (get,set) - Animate the mode widgets by regenerating a css stylesheet on every change,
+    ///   like the old versions. When disabled (the default) the opacity, blur, stretch and size are animated
+    ///   on the frame clock and drawn in the snapshot, the css is only used for the user theme
+    /// * `config-transition-styles` (get,set) - The [`TransitionStyle`] of some mode pairs in ron,
+    ///   for example `{(Minimal, Compact): (duration: 200, stretch: false), (Compact, Expanded): (duration: 600, effect: Zoom)}`.
+    ///   Setting it replaces all the styles of the mode pairs, [`set_transition_style`](ActivityWidget::set_transition_style)
+    ///   and [`unset_transition_style`](ActivityWidget::unset_transition_style) update it.
+    ///   The default style is not part of it, it's set with [`set_default_transition_style`](ActivityWidget::set_default_transition_style)
+    /// * `config-island-shape` (get,set) - The [`IslandShape`] in ron, for example `Squircle(radius: (20, 20, 36, 36), exponent: 5)`.
+    ///   The background and the mode widgets are clipped to it and the radius is animated with the size.
+    ///   The background keeps the css of `.activity-background`, its `border-radius` is ignored while a shape is set.
+    ///   Empty (the default) uses the css only
+    /// * `config-auto-collapse-timeout` (get,set) - Milliseconds without interactions before the expanded
+    ///   and overlay modes go back to compact (or minimal if there is no compact widget), 0 disables it.
+    ///   Per-mode rules set with [`set_auto_collapse`](ActivityWidget::set_auto_collapse) override it
+    ///
+    /// # Signals
+    ///
+    /// * `mode-changed(old: ActivityMode, new: ActivityMode)` - The mode changed, emitted before the transition starts
+    /// * `transition-started(target: ActivityMode)` - The css transition to a new mode started
+    /// * `transition-finished(target: ActivityMode)` - The animation of the transition ended, after the duration
+    ///   of its [`TransitionStyle`]. With `config-css-transitions` it's emitted when the size of the background settles.
+    ///   It's not emitted if another mode change interrupts the transition, the queued mode changes are applied after it
+    /// * `drag-stretch-begin(x: f64, y: f64)` - The user started stretching the widget, with the start point
+    /// * `drag-stretch-update(x: f64, y: f64)` - The drag offset changed, positive when dragging away from the center
+    /// * `drag-stretch-end(x: f64, y: f64)` - The user released the widget, the offset is 0 if the drag was cancelled
+    /// * `gesture-action(name: String)` - A gesture mapped to [`GestureAction::Signal`] was recognized
+    /// * `mode-name-changed(old: String, new: String)` - The shown mode changed, including the custom modes
+    ///
+    /// # Custom modes
+    /// Other named modes (for example "alert") can be added with [`add_custom_mode`](ActivityWidget::add_custom_mode),
+    /// each one with its own widget and [`SizeRule`]. While a custom mode is shown `mode` is its base mode,
+    /// the ActivityWidget has both the `in-<base>-mode` and `in-<name>-mode` classes and the widget has `mode-<name>`.
+    /// They are animated like the built-in modes, also with `config-css-transitions`
+    ///
+    /// # Gestures
+    /// Clicks, long presses, vertical swipes and scrolls can change the mode or emit `gesture-action`,
+    /// view [`set_gesture_action`](ActivityWidget::set_gesture_action). They don't do anything by default
+    ///
+    /// # GtkBuilder
+    /// The mode widgets can be added as typed children:
+    /// ```xml
+    /// <object class="ActivityWidget">
+    ///   <property name="mode-name">compact</property>
+    ///   <child type="minimal"><object class="GtkImage"/></child>
+    ///   <child type="compact"><object class="ScrollingLabel"/></child>
+    ///   <child type="expanded"><object class="GtkBox"/></child>
+    ///   <child type="overlay"><object class="GtkBox"/></child>
+    /// </object>
+    /// ```
     pub struct ActivityWidget(ObjectSubclass<imp::ActivityWidgetPriv>)
-        @extends gtk::Widget;
+        @extends gtk::Widget,
+        @implements gtk::Buildable;
 }
 
 impl Default for ActivityWidget {
@@ -59,16 +144,333 @@ impl ActivityWidget {
         wid
     }
 
+    /// Create a builder that validates the mode widgets and the config before building the widget
+    pub fn builder() -> ActivityWidgetBuilder {
+        ActivityWidgetBuilder::new()
+    }
+
     pub fn get_widget_for_mode(&self, mode: ActivityMode) -> Option<gtk::Widget> {
-        match mode {
-            ActivityMode::Minimal => self.minimal_mode_widget(),
-            ActivityMode::Compact => self.compact_mode_widget(),
-            ActivityMode::Expanded => self.expanded_mode_widget(),
-            ActivityMode::Overlay => self.overlay_mode_widget(),
-        }
+        self.imp().slot_widget(mode as usize)
     }
 
+    /// The widget of the shown mode, built-in or custom
     pub fn current_widget(&self) -> Option<gtk::Widget> {
-        self.get_widget_for_mode(self.mode())
+        self.imp().slot_widget(self.imp().current_slot())
+    }
+
+    /// Whether a mode transition is running
+    pub fn is_transitioning(&self) -> bool {
+        self.imp().transition.borrow().is_running()
+    }
+
+    /// What happens when the mode is changed while a transition is running
+    ///
+    /// view [`TransitionPolicy`] for more info
+    pub fn transition_policy(&self) -> TransitionPolicy {
+        self.imp().transition.borrow().policy
+    }
+
+    pub fn set_transition_policy(&self, policy: TransitionPolicy) {
+        self.imp().transition.borrow_mut().policy = policy;
+    }
+
+    /// The names of the modes (built-in or custom) waiting for the running transition to finish,
+    /// in the order they will be applied
+    ///
+    /// `mode()` is still the target of the running transition
+    pub fn pending_modes(&self) -> Vec<String> {
+        self.imp().transition.borrow().pending()
+    }
+
+    /// The transition used when the mode changes from `from` to `to`
+    pub fn transition_style(&self, from: ActivityMode, to: ActivityMode) -> TransitionStyle {
+        self.imp().transition_styles.borrow().get(from, to)
+    }
+
+    /// Use `style` when the mode changes from `from` to `to`, `config-transition-styles` is updated
+    pub fn set_transition_style(
+        &self,
+        from: ActivityMode,
+        to: ActivityMode,
+        style: TransitionStyle,
+    ) {
+        let mut pairs = self.imp().transition_styles.borrow().pairs.clone();
+        pairs.insert((from, to), style);
+        self.set_transition_pairs(&pairs);
+    }
+
+    /// Use the default style when the mode changes from `from` to `to`, `config-transition-styles` is updated
+    pub fn unset_transition_style(&self, from: ActivityMode, to: ActivityMode) {
+        let mut pairs = self.imp().transition_styles.borrow().pairs.clone();
+        if pairs.remove(&(from, to)).is_some() {
+            self.set_transition_pairs(&pairs);
+        }
+    }
+
+    /// Replace the styles of the mode pairs through `config-transition-styles`, so it's notified
+    fn set_transition_pairs(&self, pairs: &HashMap<(ActivityMode, ActivityMode), TransitionStyle>) {
+        match transition_style::serialize_styles(pairs) {
+            Ok(config) => self.set_config_transition_styles(&config),
+            Err(err) => log::warn!("failed to serialize the transition styles: {err}"),
+        }
+    }
+
+    /// The transition used by the mode pairs without a style
+    pub fn default_transition_style(&self) -> TransitionStyle {
+        self.imp().transition_styles.borrow().default
+    }
+
+    /// Use `style` for the mode pairs without a style
+    ///
+    /// `config-transition-styles` only contains the styles of the mode pairs, so it doesn't change
+    pub fn set_default_transition_style(&self, style: TransitionStyle) {
+        self.imp().transition_styles.borrow_mut().default = style;
+    }
+
+    /// The shape used to clip the island
+    pub fn island_shape(&self) -> IslandShape {
+        *self.imp().island_shape.borrow()
+    }
+
+    /// Clip the island to `shape`, the radius changes with an animation. `config-island-shape` is updated
+    pub fn set_island_shape(&self, shape: IslandShape) {
+        match shape::serialize_shape(&shape) {
+            Ok(config) => self.set_config_island_shape(&config),
+            Err(err) => log::warn!("failed to serialize the island shape: {err}"),
+        }
+    }
+
+    /// Map a gesture to an action, [`GestureAction::None`] removes the mapping
+    ///
+    /// Gestures handled by a child widget (for example a button in the expanded widget) don't reach the ActivityWidget
+    pub fn set_gesture_action(&self, gesture: Gesture, action: GestureAction) {
+        let mut gestures = self.imp().gestures.borrow_mut();
+        if action == GestureAction::None {
+            gestures.actions.remove(&gesture);
+        } else {
+            gestures.actions.insert(gesture, action);
+        }
+    }
+
+    pub fn gesture_action(&self, gesture: Gesture) -> GestureAction {
+        self.imp().gestures.borrow().get(gesture)
+    }
+
+    /// Go to `target` after `timeout` in `mode` without interactions,
+    /// a zero timeout disables the auto collapse in that mode
+    ///
+    /// The timer starts when the mode is entered, it's paused while the pointer is over the widget,
+    /// while the focus is inside it or while it's pressed or dragged, and starts again from the beginning after
+    pub fn set_auto_collapse(&self, mode: ActivityMode, timeout: Duration, target: ActivityMode) {
+        self.imp().auto_collapse.borrow_mut().rules[mode as usize] =
+            Some(AutoCollapseRule { timeout, target });
+        if self.mode() == mode {
+            auto_collapse::restart(self);
+        }
+    }
+
+    /// Remove the rule of `mode`, `config-auto-collapse-timeout` is used again
+    pub fn unset_auto_collapse(&self, mode: ActivityMode) {
+        self.imp().auto_collapse.borrow_mut().rules[mode as usize] = None;
+        if self.mode() == mode {
+            auto_collapse::restart(self);
+        }
+    }
+
+    /// The auto collapse rule used in `mode`, `None` if it's disabled
+    pub fn auto_collapse(&self, mode: ActivityMode) -> Option<AutoCollapseRule> {
+        auto_collapse::effective_rule(self, mode)
+    }
+
+    /// Add a named mode shown with [`set_custom_mode`](Self::set_custom_mode)
+    ///
+    /// `base` is the value of the `mode` property while it's shown, it's also used for the transition styles
+    /// and the auto collapse rules.
+    /// returns `Err` if the name is used by another mode, if it's not a css identifier (`[A-Za-z_-][A-Za-z0-9_-]*`,
+    /// it's used in the css classes) or if the widget already has a parent
+    pub fn add_custom_mode(
+        &self,
+        name: &str,
+        widget: &impl IsA<gtk::Widget>,
+        base: ActivityMode,
+        size_rule: SizeRule,
+    ) -> Result<()> {
+        let widget = widget.clone().upcast::<gtk::Widget>();
+        if !custom_mode::is_valid_name(name) {
+            bail!("the custom mode name {name:?} is not a valid css identifier");
+        }
+        if name.parse::<ActivityMode>().is_ok() {
+            bail!("{name} is a built-in mode");
+        }
+        if self.custom_modes().iter().any(|other| other == name) {
+            bail!("the custom mode {name} already exists");
+        }
+        if widget.parent().is_some() {
+            bail!("the widget of the custom mode {name} already has a parent");
+        }
+        widget.set_parent(self);
+        let slot = ModeSlot::custom(name, widget.clone(), base, size_rule);
+        widget.add_css_class(&slot.widget_class());
+        widget.set_overflow(gtk::Overflow::Hidden);
+        self.imp().local_css_context.borrow_mut().add_slot(name);
+        self.imp().slots.borrow_mut().push(slot);
+        self.imp().refresh_mode();
+        Ok(())
+    }
+
+    /// Remove a custom mode and return its widget,
+    /// if it's shown the ActivityWidget goes to its base mode
+    pub fn remove_custom_mode(&self, name: &str) -> Option<gtk::Widget> {
+        let imp = self.imp();
+        let slot = imp.custom_slot(name)?;
+        if imp.current_slot() == slot {
+            imp.apply_slot(self.mode() as usize);
+        }
+        let removed = imp.slots.borrow_mut().remove(slot);
+        imp.local_css_context.borrow_mut().remove_slot(slot);
+        // the next custom modes moved back by one
+        let current = imp.current_slot();
+        if current > slot {
+            imp.slot.replace(current - 1);
+        }
+        imp.transition.borrow_mut().forget(name);
+        // the values drawn for the next slots move back with them
+        animation::remove_slot(self, slot);
+        // the custom modes always have a widget
+        let widget = removed.widget.clone()?;
+        widget.unparent();
+        widget.remove_css_class(&removed.widget_class());
+        widget.remove_css_class("prev");
+        widget.remove_css_class("next");
+        self.remove_css_class(&removed.class());
+        imp.refresh_mode();
+        Some(widget)
+    }
+
+    /// Show the custom mode `name`, `set_mode` goes back to a built-in mode
+    ///
+    /// Like `set_mode`, it follows the [`TransitionPolicy`] if a transition is running
+    pub fn set_custom_mode(&self, name: &str) -> Result<()> {
+        let Some(slot) = self.imp().custom_slot(name) else {
+            bail!("the custom mode {name} doesn't exist");
+        };
+        let old_base = self.mode();
+        self.imp().request_slot(slot);
+        if old_base != self.mode() {
+            self.notify("mode");
+        }
+        Ok(())
+    }
+
+    /// The name of the custom mode that is shown, `None` if it's a built-in mode
+    pub fn custom_mode(&self) -> Option<String> {
+        let slot = self.imp().current_slot();
+        (slot >= ActivityMode::ALL.len()).then(|| self.imp().slots.borrow()[slot].name.clone())
+    }
+
+    /// The names of the custom modes, in the order they were added
+    pub fn custom_modes(&self) -> Vec<String> {
+        self.imp()
+            .slots
+            .borrow()
+            .iter()
+            .skip(ActivityMode::ALL.len())
+            .map(|custom| custom.name.clone())
+            .collect()
+    }
+
+    pub fn custom_mode_widget(&self, name: &str) -> Option<gtk::Widget> {
+        self.imp().slot_widget(self.imp().custom_slot(name)?)
+    }
+
+    pub fn connect_mode_changed<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, ActivityMode, ActivityMode) + 'static,
+    {
+        self.connect_closure(
+            "mode-changed",
+            false,
+            glib::closure_local!(move |obj: &Self, old: ActivityMode, new: ActivityMode| {
+                callback(obj, old, new)
+            }),
+        )
+    }
+
+    pub fn connect_transition_started<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, ActivityMode) + 'static,
+    {
+        self.connect_closure(
+            "transition-started",
+            false,
+            glib::closure_local!(move |obj: &Self, target: ActivityMode| callback(obj, target)),
+        )
+    }
+
+    pub fn connect_transition_finished<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, ActivityMode) + 'static,
+    {
+        self.connect_closure(
+            "transition-finished",
+            false,
+            glib::closure_local!(move |obj: &Self, target: ActivityMode| callback(obj, target)),
+        )
+    }
+
+    pub fn connect_drag_stretch_begin<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, f64, f64) + 'static,
+    {
+        self.connect_closure(
+            "drag-stretch-begin",
+            false,
+            glib::closure_local!(move |obj: &Self, x: f64, y: f64| callback(obj, x, y)),
+        )
+    }
+
+    pub fn connect_drag_stretch_update<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, f64, f64) + 'static,
+    {
+        self.connect_closure(
+            "drag-stretch-update",
+            false,
+            glib::closure_local!(move |obj: &Self, x: f64, y: f64| callback(obj, x, y)),
+        )
+    }
+
+    pub fn connect_drag_stretch_end<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, f64, f64) + 'static,
+    {
+        self.connect_closure(
+            "drag-stretch-end",
+            false,
+            glib::closure_local!(move |obj: &Self, x: f64, y: f64| callback(obj, x, y)),
+        )
+    }
+    pub fn connect_gesture_action<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, &str) + 'static,
+    {
+        self.connect_closure(
+            "gesture-action",
+            false,
+            glib::closure_local!(move |obj: &Self, name: String| callback(obj, &name)),
+        )
+    }
+
+    pub fn connect_mode_name_changed<F>(&self, callback: F) -> SignalHandlerId
+    where
+        F: Fn(&Self, &str, &str) + 'static,
+    {
+        self.connect_closure(
+            "mode-name-changed",
+            false,
+            glib::closure_local!(move |obj: &Self, old: String, new: String| {
+                callback(obj, &old, &new)
+            }),
+        )
     }
 }
@@ -10,7 +10,7 @@ use super::{imp::ActivityWidgetPriv, layout_manager::ActivityLayoutManager};
 // =============================================
 
 impl ObjectSubclass for ActivityWidgetPriv {
-    type Interfaces = ();
+    type Interfaces = (gtk::Buildable,);
     type Class = glib::subclass::basic::ClassStruct<Self>;
     type Instance = glib::subclass::basic::InstanceStruct<Self>;
     #[inline]
@@ -0,0 +1,186 @@
+use std::f64::consts::FRAC_PI_2;
+
+use abi::{gdk, gtk, log};
+use gtk::{
+    graphene::{Rect, Size},
+    gsk::{self, RoundedRect},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use super::boxed_activity_mode::ActivityMode;
+
+/// Segments used to draw each corner of a squircle
+const SQUIRCLE_SEGMENTS: u32 = 16;
+
+/// The shape of the island, used to clip the background and the mode widgets
+///
+/// The background is still drawn by the css of `.activity-background` (color, gradient or image),
+/// only its `border-radius` is replaced by the shape. Its borders and shadows are clipped too.
+///
+/// The radii are indexed by mode (Minimal, Compact, Expanded, Overlay), custom modes use the radius of their base mode.
+/// They are animated during the mode transitions and clamped to half of the shortest side
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum IslandShape {
+    /// The shape is decided by the css `border-radius` of `.activity-background`, nothing is clipped
+    #[default]
+    Css,
+    /// A rectangle with circular corners
+    RoundedRect { radius: [f64; 4] },
+    /// A rectangle with superellipse corners, the curvature changes continuously from the sides to the corners.
+    /// `exponent` is 2 for circular corners, higher values make them smoother (5 is a good start)
+    Squircle { radius: [f64; 4], exponent: f64 },
+    /// The shortest side is fully rounded in every mode
+    Pill,
+}
+
+impl IslandShape {
+    /// The corner radius of `mode` for an island of this `size`
+    pub fn radius(&self, mode: ActivityMode, size: (f64, f64)) -> f64 {
+        let max = size.0.min(size.1) / 2.0;
+        let radius = match self {
+            IslandShape::Css => 0.0,
+            IslandShape::RoundedRect { radius } | IslandShape::Squircle { radius, .. } => {
+                radius[mode as usize]
+            }
+            IslandShape::Pill => max,
+        };
+        radius.clamp(0.0, max.max(0.0))
+    }
+}
+
+/// Parse the value of `config-island-shape`, an empty string is [`IslandShape::Css`]
+///
+/// # Example
+/// ```ron
+/// Squircle(radius: (20, 20, 36, 36), exponent: 5)
+/// ```
+pub(super) fn parse_shape(config: &str) -> Result<IslandShape, ron::error::SpannedError> {
+    if config.trim().is_empty() {
+        return Ok(IslandShape::Css);
+    }
+    ron::from_str(config)
+}
+
+/// Write `shape` in the format of `config-island-shape`
+pub(super) fn serialize_shape(shape: &IslandShape) -> Result<String, ron::Error> {
+    ron::to_string(shape)
+}
+
+/// The mask of the last squircle drawn, it's only rasterized again when the size, the radius or the exponent change
+#[derive(Default)]
+pub(super) struct SquircleMask {
+    /// (size, radius, exponent)
+    key: Option<((f64, f64), f64, f64)>,
+    node: Option<gsk::CairoNode>,
+}
+
+impl SquircleMask {
+    fn get(&mut self, size: (f64, f64), radius: f64, exponent: f64) -> gsk::CairoNode {
+        let key = (size, radius, exponent);
+        if let Some(node) = self.node.as_ref().filter(|_| self.key == Some(key)) {
+            return node.clone();
+        }
+        let node = gsk::CairoNode::new(&Rect::new(0.0, 0.0, size.0 as f32, size.1 as f32));
+        let cr = node.draw_context();
+        squircle_path(&cr, size, radius, exponent);
+        cr.set_source_rgba(0.0, 0.0, 0.0, 1.0);
+        if let Err(err) = cr.fill() {
+            log::warn!("failed to draw the island shape: {err}");
+        }
+        // the node can only be used after the drawing is finished
+        drop(cr);
+        self.key = Some(key);
+        self.node = Some(node.clone());
+        node
+    }
+}
+
+/// Start clipping to `shape` with the corner `radius`, it needs to be ended with [`pop_clip`]
+///
+/// The squircle mask is taken from `mask` when it didn't change since the last frame.
+/// returns `false` if nothing was pushed (`IslandShape::Css`)
+pub(super) fn push_clip(
+    snapshot: &gtk::Snapshot,
+    shape: &IslandShape,
+    size: (f64, f64),
+    radius: f64,
+    mask: &mut SquircleMask,
+) -> bool {
+    let bounds = Rect::new(0.0, 0.0, size.0 as f32, size.1 as f32);
+    let radius = radius.clamp(0.0, (size.0.min(size.1) / 2.0).max(0.0));
+    match shape {
+        IslandShape::Css => false,
+        IslandShape::RoundedRect { .. } | IslandShape::Pill => {
+            let corner = Size::new(radius as f32, radius as f32);
+            snapshot.push_rounded_clip(&RoundedRect::new(bounds, corner, corner, corner, corner));
+            true
+        }
+        IslandShape::Squircle { exponent, .. } => {
+            snapshot.push_mask(gsk::MaskMode::Alpha);
+            snapshot.append_node(mask.get(size, radius, *exponent));
+            // the mask is recorded, the clipped content follows
+            snapshot.pop();
+            true
+        }
+    }
+}
+
+/// End the clip started by [`push_clip`]
+pub(super) fn pop_clip(snapshot: &gtk::Snapshot) {
+    snapshot.pop();
+}
+
+/// A rectangle with superellipse corners of size `radius`
+fn squircle_path(cr: &gdk::cairo::Context, size: (f64, f64), radius: f64, exponent: f64) {
+    let (width, height) = size;
+    let power = 2.0 / exponent.max(1.0);
+    // (corner center, direction of the x and y offsets), clockwise from the top right
+    let corners = [
+        ((width - radius, radius), (1.0, -1.0)),
+        ((width - radius, height - radius), (1.0, 1.0)),
+        ((radius, height - radius), (-1.0, 1.0)),
+        ((radius, radius), (-1.0, -1.0)),
+    ];
+    cr.new_path();
+    cr.move_to(radius, 0.0);
+    for (i, ((center_x, center_y), (dir_x, dir_y))) in corners.into_iter().enumerate() {
+        for step in 0..=SQUIRCLE_SEGMENTS {
+            // every corner goes from the side before it to the side after it, clockwise
+            let t = step as f64 / SQUIRCLE_SEGMENTS as f64 * FRAC_PI_2;
+            let (along_x, along_y) = if i % 2 == 0 {
+                (t.sin(), t.cos())
+            } else {
+                (t.cos(), t.sin())
+            };
+            cr.line_to(
+                center_x + dir_x * radius * along_x.powf(power),
+                center_y + dir_y * radius * along_y.powf(power),
+            );
+        }
+    }
+    cr.close_path();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_shape_parses_back() {
+        for shape in [
+            IslandShape::Css,
+            IslandShape::Pill,
+            IslandShape::RoundedRect {
+                radius: [10.0, 20.0, 30.0, 40.0],
+            },
+            IslandShape::Squircle {
+                radius: [20.0, 20.0, 36.0, 36.0],
+                exponent: 5.0,
+            },
+        ] {
+            let config = serialize_shape(&shape).unwrap();
+            assert_eq!(parse_shape(&config).unwrap(), shape);
+        }
+    }
+}
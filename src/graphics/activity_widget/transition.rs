@@ -0,0 +1,310 @@
+use std::{cell::Cell, collections::VecDeque};
+
+use abi::{glib, gtk};
+use gtk::{prelude::*, subclass::prelude::*, TickCallbackId};
+
+use super::{animation, boxed_activity_mode::ActivityMode, util, ActivityWidget};
+
+/// Consecutive frames with the same size needed to consider the css size animation settled
+const STABLE_FRAMES: u32 = 3;
+/// If the size never changes (for example between two modes with the same size),
+/// the transition is considered finished after this time, in microseconds
+const STATIC_TRANSITION_TIME: i64 = 500_000;
+
+/// What happens when the mode is changed while a transition is running
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TransitionPolicy {
+    /// Start the new transition immediately from the current state, the running one never finishes
+    #[default]
+    Interrupt,
+    /// Wait for the running transition to finish, then go through every requested mode in order
+    Queue,
+    /// Wait for the running transition to finish, then go to the last requested mode only
+    Collapse,
+}
+
+/// What to do with a mode change request, decided by [`TransitionTracker::request`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Request {
+    /// Start the transition to the requested mode now
+    Apply,
+    /// The requested mode is already shown (or is the target of the running transition),
+    /// only its layout is updated
+    Refresh,
+    /// The mode is applied when the running transition finishes
+    Wait,
+}
+
+/// The state machine of the mode transitions
+///
+/// # Phases and css classes
+/// * Idle - the `ActivityWidget` has `in-<mode>-mode`, the current mode widget has `next`
+/// * Running - the `ActivityWidget` also has `transitioning`,
+///   the target mode widget has `next` and the previous mode widget has `prev`
+/// * Pending - while a queued or collapsed mode change is waiting, the `ActivityWidget` also has `transition-pending`
+///
+/// No other mode widget has `prev` or `next` in any phase
+#[derive(Default)]
+pub(super) struct TransitionTracker {
+    tick: Option<TickCallbackId>,
+    target: Option<ActivityMode>,
+    pub(super) policy: TransitionPolicy,
+    /// The names of the requested modes, built-in or custom
+    pending: VecDeque<String>,
+}
+
+impl TransitionTracker {
+    /// Whether a transition is running
+    pub(super) fn is_running(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// The mode changes waiting for the running transition to finish
+    pub(super) fn pending(&self) -> Vec<String> {
+        self.pending.iter().cloned().collect()
+    }
+
+    /// Decide what to do with a request to show `mode` while `current` is shown (or is the target of the running transition)
+    ///
+    /// The pending modes are updated according to the policy
+    pub(super) fn request(&mut self, current: &str, mode: &str) -> Request {
+        let refresh = mode == current;
+        if !self.is_running() {
+            return if refresh {
+                Request::Refresh
+            } else {
+                Request::Apply
+            };
+        }
+        match self.policy {
+            TransitionPolicy::Interrupt => {
+                self.pending.clear();
+                if refresh {
+                    Request::Refresh
+                } else {
+                    Request::Apply
+                }
+            }
+            TransitionPolicy::Queue => {
+                if refresh && self.pending.is_empty() {
+                    Request::Refresh
+                } else {
+                    if self.pending.back().map(String::as_str) != Some(mode) {
+                        self.pending.push_back(mode.to_string());
+                    }
+                    Request::Wait
+                }
+            }
+            TransitionPolicy::Collapse => {
+                self.pending.clear();
+                if refresh {
+                    Request::Refresh
+                } else {
+                    self.pending.push_back(mode.to_string());
+                    Request::Wait
+                }
+            }
+        }
+    }
+
+    /// Drop the pending changes to a custom mode that was removed
+    pub(super) fn forget(&mut self, name: &str) {
+        self.pending.retain(|pending| pending != name);
+    }
+}
+
+/// Decide what to do with a request to show the mode `mode` (built-in or custom) according to the policy
+pub(super) fn request(obj: &ActivityWidget, mode: &str) -> Request {
+    let current = obj.imp().mode_name();
+    let mut transition = obj.imp().transition.borrow_mut();
+    let request = transition.request(&current, mode);
+    let has_pending = !transition.pending.is_empty();
+    drop(transition);
+    set_class(obj, "transition-pending", has_pending);
+    request
+}
+
+/// Emit `transition-started`, `transition-finished` is emitted when the animation of the mode widgets ends
+///
+/// With `config-css-transitions` the animation is done by the css, so the size of the background
+/// is watched on the frame clock instead.
+/// A transition that is still running is stopped without emitting `transition-finished`
+pub(super) fn start(obj: &ActivityWidget, target: ActivityMode) {
+    stop_tick(obj);
+    obj.imp().transition.borrow_mut().target = Some(target);
+    obj.add_css_class("transitioning");
+    obj.emit_by_name::<()>("transition-started", &[&target]);
+    if !obj.imp().transition.borrow().is_running() {
+        // a handler of transition-started already finished it
+        return;
+    }
+    if !obj.is_mapped() {
+        // there is no frame clock, nothing will be animated
+        finish(obj);
+    } else if obj.config_css_transitions() {
+        watch_css_transition(obj);
+    } else if !animation::is_running(obj) {
+        // the mode widgets jumped to their final values
+        finish(obj);
+    }
+}
+
+/// End the running transition, called when the animation of the mode widgets ends or is stopped
+pub(super) fn animation_ended(obj: &ActivityWidget) {
+    if obj.imp().transition.borrow().is_running() {
+        finish(obj);
+    }
+}
+
+/// Finish the transition when the size of the background (animated by the css) settles
+fn watch_css_transition(obj: &ActivityWidget) {
+    let last_size = Cell::new((obj.width(), obj.height()));
+    let size_changed = Cell::new(false);
+    let stable_frames = Cell::new(0);
+    let start_time = Cell::new(None);
+    let tick = obj.add_tick_callback(move |obj, frame_clock| {
+        let now = util::frame_time(frame_clock);
+        let start = start_time.get().unwrap_or(now);
+        start_time.set(Some(start));
+
+        let size = (obj.width(), obj.height());
+        if size != last_size.replace(size) {
+            size_changed.set(true);
+            stable_frames.set(0);
+        } else {
+            stable_frames.set(stable_frames.get() + 1);
+        }
+        let settled = if size_changed.get() {
+            stable_frames.get() >= STABLE_FRAMES
+        } else {
+            now - start >= STATIC_TRANSITION_TIME
+        };
+        if !settled {
+            return glib::ControlFlow::Continue;
+        }
+        obj.imp().transition.borrow_mut().tick = None;
+        finish(obj);
+        glib::ControlFlow::Break
+    });
+    obj.imp().transition.borrow_mut().tick = Some(tick);
+}
+
+fn stop_tick(obj: &ActivityWidget) {
+    if let Some(tick) = obj.imp().transition.borrow_mut().tick.take() {
+        tick.remove();
+    }
+}
+
+/// Go back to idle, emit `transition-finished` and apply the next pending mode
+fn finish(obj: &ActivityWidget) {
+    stop_tick(obj);
+    let target = obj.imp().transition.borrow_mut().target.take();
+    obj.remove_css_class("transitioning");
+    for widget in obj.imp().all_mode_widgets() {
+        widget.remove_css_class("prev");
+    }
+    if let Some(target) = target {
+        obj.emit_by_name::<()>("transition-finished", &[&target]);
+    }
+
+    let next = {
+        let mut transition = obj.imp().transition.borrow_mut();
+        // the handlers of transition-finished could have started another transition
+        if transition.is_running() {
+            None
+        } else {
+            let current = obj.imp().mode_name();
+            // a change to the current mode wouldn't start a transition
+            while transition.pending.front() == Some(&current) {
+                transition.pending.pop_front();
+            }
+            transition.pending.pop_front()
+        }
+    };
+    let has_pending = !obj.imp().transition.borrow().pending.is_empty();
+    set_class(obj, "transition-pending", has_pending);
+    if let Some(next) = next {
+        obj.set_mode_name(&next);
+    }
+}
+
+fn set_class(obj: &ActivityWidget, class: &str, enabled: bool) {
+    if enabled {
+        obj.add_css_class(class);
+    } else {
+        obj.remove_css_class(class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(policy: TransitionPolicy) -> TransitionTracker {
+        TransitionTracker {
+            target: Some(ActivityMode::Compact),
+            policy,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn idle_applies_every_policy() {
+        for policy in [
+            TransitionPolicy::Interrupt,
+            TransitionPolicy::Queue,
+            TransitionPolicy::Collapse,
+        ] {
+            let mut tracker = TransitionTracker {
+                policy,
+                ..Default::default()
+            };
+            assert_eq!(tracker.request("minimal", "compact"), Request::Apply);
+            assert_eq!(tracker.request("compact", "compact"), Request::Refresh);
+            assert!(tracker.pending().is_empty());
+        }
+    }
+
+    #[test]
+    fn interrupt_applies_while_running() {
+        let mut tracker = running(TransitionPolicy::Interrupt);
+        assert_eq!(tracker.request("compact", "expanded"), Request::Apply);
+        assert_eq!(tracker.request("compact", "compact"), Request::Refresh);
+        assert!(tracker.pending().is_empty());
+    }
+
+    #[test]
+    fn queue_keeps_every_mode_in_order() {
+        let mut tracker = running(TransitionPolicy::Queue);
+        // a refresh of the target doesn't wait
+        assert_eq!(tracker.request("compact", "compact"), Request::Refresh);
+        assert_eq!(tracker.request("compact", "expanded"), Request::Wait);
+        // the same mode twice in a row is queued once
+        assert_eq!(tracker.request("compact", "expanded"), Request::Wait);
+        assert_eq!(tracker.request("compact", "alert"), Request::Wait);
+        // going back to the target after the queue is a real change
+        assert_eq!(tracker.request("compact", "compact"), Request::Wait);
+        assert_eq!(tracker.pending(), ["expanded", "alert", "compact"]);
+    }
+
+    #[test]
+    fn collapse_keeps_the_last_mode() {
+        let mut tracker = running(TransitionPolicy::Collapse);
+        assert_eq!(tracker.request("compact", "expanded"), Request::Wait);
+        assert_eq!(tracker.request("compact", "overlay"), Request::Wait);
+        assert_eq!(tracker.pending(), ["overlay"]);
+        // asking for the target again cancels the pending change
+        assert_eq!(tracker.request("compact", "compact"), Request::Refresh);
+        assert!(tracker.pending().is_empty());
+    }
+
+    #[test]
+    fn forget_removes_pending_custom_modes() {
+        let mut tracker = running(TransitionPolicy::Queue);
+        tracker.request("compact", "alert");
+        tracker.request("compact", "expanded");
+        tracker.request("compact", "alert");
+        tracker.forget("alert");
+        assert_eq!(tracker.pending(), ["expanded"]);
+    }
+}
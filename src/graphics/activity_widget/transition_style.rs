@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use super::boxed_activity_mode::ActivityMode;
+
+/// How the progress of a transition is mapped to the animated values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    #[default]
+    EaseInOut,
+}
+
+impl Easing {
+    /// Map `t` (0.0-1.0) to the progress of the animation
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+        }
+    }
+}
+
+/// How the widgets of the two modes are swapped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransitionEffect {
+    /// The old widget fades out while the new one fades in
+    #[default]
+    Crossfade,
+    /// The new widget comes from the bottom and pushes the old one up,
+    /// in a vertical island it comes from the right and pushes the old one to the left
+    SlideUp,
+    /// The new widget comes from the top and pushes the old one down,
+    /// in a vertical island it comes from the left and pushes the old one to the right
+    SlideDown,
+    /// The new widget grows into place while the old one grows and fades out
+    Zoom,
+    /// The widgets are swapped immediately, only the size is animated
+    MorphOnly,
+}
+
+/// The animation used when changing from a mode to another
+///
+/// Only `blur` and `stretch` are used with `config-css-transitions`,
+/// the rest of the animation is defined by the css in that case
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransitionStyle {
+    /// Duration in milliseconds
+    pub duration: u64,
+    pub easing: Easing,
+    /// Blur radius of the hidden widgets, `None` uses `config-blur-radius`
+    pub blur: Option<f64>,
+    /// Whether the widgets are stretched to the size of the island during the transition
+    pub stretch: bool,
+    pub effect: TransitionEffect,
+}
+
+impl Default for TransitionStyle {
+    fn default() -> Self {
+        Self {
+            duration: 400,
+            easing: Easing::default(),
+            blur: None,
+            stretch: true,
+            effect: TransitionEffect::default(),
+        }
+    }
+}
+
+/// The transition styles of an ActivityWidget
+#[derive(Clone, Debug, Default)]
+pub(super) struct TransitionStyles {
+    pub(super) default: TransitionStyle,
+    pub(super) pairs: HashMap<(ActivityMode, ActivityMode), TransitionStyle>,
+}
+
+impl TransitionStyles {
+    pub(super) fn get(&self, from: ActivityMode, to: ActivityMode) -> TransitionStyle {
+        self.pairs.get(&(from, to)).copied().unwrap_or(self.default)
+    }
+}
+
+/// Parse the value of `config-transition-styles`, an empty string doesn't contain any style
+///
+/// # Example
+/// ```ron
+/// {
+///     (Minimal, Compact): (duration: 200, blur: Some(0.0), stretch: false),
+///     (Compact, Expanded): (duration: 600, easing: EaseOut, effect: Zoom),
+/// }
+/// ```
+pub(super) fn parse_styles(
+    config: &str,
+) -> Result<HashMap<(ActivityMode, ActivityMode), TransitionStyle>, ron::error::SpannedError> {
+    if config.trim().is_empty() {
+        return Ok(HashMap::new());
+    }
+    ron::from_str(config)
+}
+
+/// Write the styles of the mode pairs in the format of `config-transition-styles`, sorted by mode pair
+pub(super) fn serialize_styles(
+    pairs: &HashMap<(ActivityMode, ActivityMode), TransitionStyle>,
+) -> Result<String, ron::Error> {
+    struct SortedPairs<'a>(Vec<(&'a (ActivityMode, ActivityMode), &'a TransitionStyle)>);
+
+    impl Serialize for SortedPairs<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(self.0.iter().copied())
+        }
+    }
+
+    let mut sorted: Vec<_> = pairs.iter().collect();
+    sorted.sort_by_key(|((from, to), _)| (*from as u8, *to as u8));
+    ron::to_string(&SortedPairs(sorted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialized_styles_parse_back() {
+        let mut pairs = HashMap::new();
+        pairs.insert(
+            (ActivityMode::Compact, ActivityMode::Expanded),
+            TransitionStyle {
+                duration: 600,
+                effect: TransitionEffect::Zoom,
+                ..Default::default()
+            },
+        );
+        pairs.insert(
+            (ActivityMode::Minimal, ActivityMode::Compact),
+            TransitionStyle {
+                blur: Some(0.0),
+                stretch: false,
+                ..Default::default()
+            },
+        );
+        let config = serialize_styles(&pairs).unwrap();
+        assert!(config.find("(Minimal,Compact)") < config.find("(Compact,Expanded)"));
+        assert_eq!(parse_styles(&config).unwrap(), pairs);
+        assert_eq!(serialize_styles(&HashMap::new()).unwrap(), "{}");
+    }
+}
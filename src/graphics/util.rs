@@ -1,10 +1,10 @@
-use std::{fmt::Display, str::FromStr};
+use std::{cell::Cell, fmt::Display, str::FromStr};
 
 use abi::{gdk, gtk};
-use gdk::prelude::{DisplayExt, ListModelExtManual, MonitorExt};
+use gdk::prelude::{DisplayExt, FrameClockExt, ListModelExtManual, MonitorExt};
 use gtk::{graphene::Point, gsk::Transform, prelude::WidgetExt};
 
-use super::activity_widget::boxed_activity_mode::ActivityMode;
+use super::activity_widget::custom_mode::SizeRule;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CssSize {
@@ -51,29 +51,81 @@ impl CssSize {
     }
 }
 
+thread_local! {
+    /// A frame time used instead of the one of the frame clocks, set by the snapshot helpers
+    /// to draw the animations at a known time
+    static FIXED_FRAME_TIME: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+/// The frame time (in microseconds) to use in a tick callback
+///
+/// Every animation should get the time from here instead of `frame_clock.frame_time()`
+pub(crate) fn frame_time(frame_clock: &gdk::FrameClock) -> i64 {
+    FIXED_FRAME_TIME
+        .with(Cell::get)
+        .unwrap_or_else(|| frame_clock.frame_time())
+}
+
+/// The current frame time of the frame clock of `widget`, `None` if it isn't realized
+pub(crate) fn widget_frame_time(widget: &impl WidgetExt) -> Option<i64> {
+    widget
+        .frame_clock()
+        .map(|frame_clock| frame_time(&frame_clock))
+}
+
+/// Replace the frame time of every animation on this thread, `None` goes back to the frame clocks
+#[cfg(feature = "testing")]
+pub(crate) fn set_fixed_frame_time(time: Option<i64>) {
+    FIXED_FRAME_TIME.with(|fixed| fixed.set(time));
+}
+
 /// Get the size of a widget for a mode
 ///
 /// For `Minimal` mode there is a forced height and width (`minimal_height` and `minimal_width`)
 ///
 /// For `Compact` mode there is only a forced height (`minimal_height`)
 ///
+/// The custom modes use their [`SizeRule`], a `Fixed` size is used as is
+///
+/// In a `Vertical` island the axes are swapped: `minimal_height` is the forced width (the thickness of the island)
+/// and `minimal_width` is the forced height of `Minimal` mode
+///
 /// If a size isn't forced the final size is the requested size.
 ///
 /// If the requested size isn't set (or is -1), the natural size is used,
 /// this is calculated from `widget.measure(gtk::Orientation, -1)`
 pub(super) fn get_final_widget_size(
     widget: &gtk::Widget,
-    mode: ActivityMode,
+    rule: impl Into<SizeRule>,
+    orientation: gtk::Orientation,
     minimal_height: i32,
     minimal_width: i32,
 ) -> (i32, i32) {
-    let force_height = matches!(mode, ActivityMode::Minimal | ActivityMode::Compact);
-    let force_width = matches!(mode, ActivityMode::Minimal);
+    let rule = rule.into();
+    let vertical = orientation == gtk::Orientation::Vertical;
+    let (minimal_width, minimal_height) = if vertical {
+        (minimal_height, minimal_width)
+    } else {
+        (minimal_width, minimal_height)
+    };
+    if let SizeRule::Fixed { width, height } = rule {
+        return (width.max(minimal_width), height.max(minimal_height));
+    }
+    let force_thickness = matches!(rule, SizeRule::Minimal | SizeRule::MinimalHeight);
+    let force_length = matches!(rule, SizeRule::Minimal);
+    let (force_width, force_height) = if vertical {
+        (force_thickness, force_length)
+    } else {
+        (force_length, force_thickness)
+    };
     let measured_width = widget.measure(
         gtk::Orientation::Horizontal,
         if force_height { minimal_height } else { -1 },
     );
-    let measured_height = widget.measure(gtk::Orientation::Vertical, -1);
+    let measured_height = widget.measure(
+        gtk::Orientation::Vertical,
+        if force_width { minimal_width } else { -1 },
+    );
     let height = if force_height {
         minimal_height
     } else if widget.height_request() > 0 {
@@ -97,32 +149,52 @@ pub(super) fn get_final_widget_size(
 /// the widget is always centered
 ///
 /// otherwise the child size is used
+///
+/// `minimal_height` is the thickness of the island, it's a width if `orientation` is `Vertical`
 pub(super) fn get_child_aligned_allocation(
     parent_allocation: (i32, i32, i32),
     child: &gtk::Widget,
-    mode: ActivityMode,
+    rule: impl Into<SizeRule>,
+    orientation: gtk::Orientation,
     minimal_height: i32,
     _is_dragging: bool,
 ) -> (i32, i32, Option<Transform>) {
+    let rule = rule.into();
     let parent_width = parent_allocation.0;
     let parent_height = parent_allocation.1;
     let _parent_baseline = parent_allocation.2;
     let requests_height = child.height_request() != -1;
     let requests_width = child.width_request() != -1;
 
-    let force_height = matches!(mode, ActivityMode::Minimal | ActivityMode::Compact);
+    let force_thickness = matches!(rule, SizeRule::Minimal | SizeRule::MinimalHeight);
+    let vertical = orientation == gtk::Orientation::Vertical;
     let (child_width_min, child_width_nat, _, _) = child.measure(
         gtk::Orientation::Horizontal,
-        if force_height { minimal_height } else { -1 },
+        if force_thickness && !vertical {
+            minimal_height
+        } else {
+            -1
+        },
+    );
+    let (child_height_min, child_height_nat, _, _) = child.measure(
+        gtk::Orientation::Vertical,
+        if force_thickness && vertical {
+            minimal_height
+        } else {
+            -1
+        },
     );
-    let (child_height_min, child_height_nat, _, _) = child.measure(gtk::Orientation::Vertical, -1);
 
-    let child_width = if requests_width {
+    let child_width = if let SizeRule::Fixed { width, .. } = rule {
+        width.max(child_width_min)
+    } else if requests_width {
         parent_width.clamp(child_width_min, child_width_nat)
     } else {
         child_width_nat
     };
-    let child_height = if requests_height {
+    let child_height = if let SizeRule::Fixed { height, .. } = rule {
+        height.max(child_height_min)
+    } else if requests_height {
         parent_height.clamp(child_height_min, child_height_nat)
     } else {
         child_height_nat
@@ -168,18 +240,16 @@ pub(super) fn get_child_aligned_allocation(
     (width, height, opt_transform)
 }
 
-/// Get a slice where every value is `other_values` except the one at the index of `mode` that is `mode_value`
-pub(super) fn get_property_slice_for_mode_f64(
-    mode: ActivityMode,
-    mode_value: f64,
+/// Get a list of `slots` values where every value is `other_values` except the one at index `slot` that is `slot_value`
+pub(super) fn get_property_slice_for_slot_f64(
+    slots: usize,
+    slot: usize,
+    slot_value: f64,
     other_values: f64,
-) -> [f64; 4] {
-    match mode {
-        ActivityMode::Minimal => [mode_value, other_values, other_values, other_values],
-        ActivityMode::Compact => [other_values, mode_value, other_values, other_values],
-        ActivityMode::Expanded => [other_values, other_values, mode_value, other_values],
-        ActivityMode::Overlay => [other_values, other_values, other_values, mode_value],
-    }
+) -> Vec<f64> {
+    (0..slots)
+        .map(|i| if i == slot { slot_value } else { other_values })
+        .collect()
 }
 //TODO listen for added monitors, cache result
 pub fn get_max_monitors_size() -> (i32, i32) {
@@ -1,10 +1,22 @@
+pub mod activity_definition;
+pub mod activity_lifecycle;
 pub mod activity_map;
+mod activity_scheduler;
 pub mod base_module;
+mod config_tree;
 pub mod dynamic_activity;
 pub mod dynamic_property;
 pub mod graphics;
+pub mod module_command;
+pub mod module_config;
+pub mod module_logger;
+pub mod registration_handle;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transient_activity;
 
 pub extern crate dynisland_abi as abi;
 #[cfg(feature = "macro")]
 pub extern crate dynisland_macro as d_macro;
 pub extern crate ron;
+pub extern crate serde;
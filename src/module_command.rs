@@ -0,0 +1,254 @@
+use std::{ffi::OsString, future::Future, pin::Pin, sync::Arc};
+
+use abi::log;
+use anyhow::{anyhow, Result};
+use clap::{error::ErrorKind, Subcommand};
+
+use crate::base_module::ProducerRuntime;
+
+type CommandFuture = Pin<Box<dyn Future<Output = Result<String>> + Send>>;
+type CommandHandler<S> = Arc<dyn Fn(S) -> CommandFuture + Send + Sync>;
+
+enum ParsedCommand<S> {
+    Command(S),
+    /// `--help` or `--version` was requested
+    Output(String),
+}
+
+/// Parses the cli commands of a module and runs them on the `ProducerRuntime`
+///
+/// The commands are declared with a clap `Subcommand` enum,
+/// `--help` for the module and every command is generated by clap.
+///
+/// # Example
+/// ```ignore
+/// #[derive(clap::Subcommand)]
+/// enum TimerCommand {
+///     /// Start a new timer
+///     Start { duration: String },
+///     Stop,
+/// }
+///
+/// let router = CommandRouter::new("timer", |command: TimerCommand| async move {
+///     match command {
+///         TimerCommand::Start { duration } => Ok(format!("started a timer for {duration}")),
+///         TimerCommand::Stop => Ok("stopped".to_string()),
+///     }
+/// });
+/// // `dynisland module timer start 5m`
+/// let output = router.dispatch_blocking(&producers_rt, ["start", "5m"]);
+/// ```
+pub struct CommandRouter<S: Subcommand> {
+    command: clap::Command,
+    handler: CommandHandler<S>,
+}
+
+impl<S: Subcommand> Clone for CommandRouter<S> {
+    fn clone(&self) -> Self {
+        Self {
+            command: self.command.clone(),
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<S: Subcommand + Send + 'static> CommandRouter<S> {
+    /// Create a new router
+    ///
+    /// * `module_name` - the name of the module, it's used in the help messages
+    /// * `handler` - the async function that executes the command, the returned string is the output of the command
+    pub fn new<F, Fut>(module_name: &str, handler: F) -> Self
+    where
+        F: Fn(S) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let command = clap::Command::new(module_name.to_string())
+            .bin_name(format!("dynisland module {module_name}"))
+            .no_binary_name(true)
+            .subcommand_required(true)
+            .arg_required_else_help(true);
+        Self {
+            command: S::augment_subcommands(command),
+            handler: Arc::new(move |cmd| Box::pin(handler(cmd))),
+        }
+    }
+
+    /// Get the help message of the module
+    pub fn help(&self) -> String {
+        self.command.clone().render_help().to_string()
+    }
+
+    /// Parse the arguments and run the command on the runtime
+    ///
+    /// returns the output of the command, or the help message if it was requested
+    ///
+    /// returns `Err` with the formatted clap error if the arguments are invalid, or the error returned by the command
+    pub async fn dispatch<I, T>(&self, runtime: &ProducerRuntime, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let command = match self.parse(args)? {
+            ParsedCommand::Command(command) => command,
+            ParsedCommand::Output(output) => return Ok(output),
+        };
+        let handle = runtime.handle_async().await;
+        handle
+            .spawn((self.handler)(command))
+            .await
+            .map_err(|err| anyhow!("command failed: {err}"))?
+    }
+
+    /// Parse the arguments and run the command on the runtime
+    ///
+    /// returns the output of the command, or the help message if it was requested
+    ///
+    /// returns `Err` with the formatted clap error if the arguments are invalid, or the error returned by the command
+    ///
+    /// blocking
+    pub fn dispatch_blocking<I, T>(&self, runtime: &ProducerRuntime, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let command = match self.parse(args)? {
+            ParsedCommand::Command(command) => command,
+            ParsedCommand::Output(output) => return Ok(output),
+        };
+        let (res_tx, res_rx) = tokio::sync::oneshot::channel();
+        let fut = (self.handler)(command);
+        runtime.handle().spawn(async move {
+            if res_tx.send(fut.await).is_err() {
+                log::debug!("command result receiver dropped");
+            }
+        });
+        res_rx
+            .blocking_recv()
+            .map_err(|_| anyhow!("command failed: the producer runtime stopped"))?
+    }
+
+    fn parse<I, T>(&self, args: I) -> Result<ParsedCommand<S>>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = match self.command.clone().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(err) => {
+                return match err.kind() {
+                    ErrorKind::DisplayHelp
+                    | ErrorKind::DisplayVersion
+                    | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand => {
+                        Ok(ParsedCommand::Output(err.render().to_string()))
+                    }
+                    _ => Err(anyhow!(err.render().to_string())),
+                };
+            }
+        };
+        S::from_arg_matches(&matches)
+            .map(ParsedCommand::Command)
+            .map_err(|err| anyhow!(err.render().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use clap::Subcommand;
+
+    use super::CommandRouter;
+    use crate::base_module::ProducerRuntime;
+
+    #[derive(Subcommand, Debug)]
+    enum TimerCommand {
+        /// Start a new timer
+        Start {
+            duration: String,
+        },
+        /// Stop the timer
+        Stop {
+            #[arg(long)]
+            reset: bool,
+        },
+        Fail,
+    }
+
+    fn router() -> CommandRouter<TimerCommand> {
+        CommandRouter::new("timer", |command: TimerCommand| async move {
+            match command {
+                TimerCommand::Start { duration } => Ok(format!("started a timer for {duration}")),
+                TimerCommand::Stop { reset } => Ok(format!("stopped, reset: {reset}")),
+                TimerCommand::Fail => bail!("the timer failed"),
+            }
+        })
+    }
+
+    #[test]
+    fn commands_are_parsed_and_run() {
+        let runtime = ProducerRuntime::new();
+        let router = router();
+        assert_eq!(
+            router.dispatch_blocking(&runtime, ["start", "5m"]).unwrap(),
+            "started a timer for 5m"
+        );
+        assert_eq!(
+            router
+                .dispatch_blocking(&runtime, ["stop", "--reset"])
+                .unwrap(),
+            "stopped, reset: true"
+        );
+        runtime.shutdown_blocking();
+    }
+
+    #[test]
+    fn async_dispatch_runs_on_the_producer_runtime() {
+        let runtime = ProducerRuntime::new();
+        let router = router();
+        let output = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(router.dispatch(&runtime, ["stop"]));
+        assert_eq!(output.unwrap(), "stopped, reset: false");
+        runtime.shutdown_blocking();
+    }
+
+    #[test]
+    fn command_errors_are_returned() {
+        let runtime = ProducerRuntime::new();
+        let err = router().dispatch_blocking(&runtime, ["fail"]).unwrap_err();
+        assert_eq!(err.to_string(), "the timer failed");
+        runtime.shutdown_blocking();
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        let runtime = ProducerRuntime::new();
+        let router = router();
+        let err = router.dispatch_blocking(&runtime, ["pause"]).unwrap_err();
+        assert!(err.to_string().contains("unrecognized subcommand 'pause'"));
+        let err = router.dispatch_blocking(&runtime, ["start"]).unwrap_err();
+        assert!(err.to_string().contains("<DURATION>"));
+        runtime.shutdown_blocking();
+    }
+
+    #[test]
+    fn help_is_returned_as_output() {
+        let runtime = ProducerRuntime::new();
+        let router = router();
+        let help = router.dispatch_blocking(&runtime, ["--help"]).unwrap();
+        assert!(help.contains("Usage: dynisland module timer"));
+        assert!(help.contains("Start a new timer"));
+        assert_eq!(help, router.help());
+        // without a command the help is shown too
+        let help = router
+            .dispatch_blocking(&runtime, Vec::<String>::new())
+            .unwrap();
+        assert!(help.contains("Usage: dynisland module timer"));
+
+        let help = router
+            .dispatch_blocking(&runtime, ["start", "--help"])
+            .unwrap();
+        assert!(help.contains("Usage: dynisland module timer start <DURATION>"));
+        runtime.shutdown_blocking();
+    }
+}
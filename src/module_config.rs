@@ -0,0 +1,567 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display, path::Path, rc::Rc};
+
+use abi::{glib, log, module::ActivityIdentifier};
+use anyhow::{anyhow, Context, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use ron::error::SpannedError;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{base_module::BaseModule, config_tree::ConfigTree};
+
+/// An error found while loading a config, with the position in the source if it's known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    /// `(line, column)` of the error in the config source
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, col)) => write!(f, "{}:{}: {}", line, col, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<SpannedError> for ConfigError {
+    fn from(err: SpannedError) -> Self {
+        Self {
+            position: Some((err.position.line, err.position.col)),
+            message: err.code.to_string(),
+        }
+    }
+}
+
+impl From<ron::Error> for ConfigError {
+    fn from(err: ron::Error) -> Self {
+        Self {
+            position: None,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// The field of the user config containing the per-window overrides
+///
+/// example: `(scroll_speed: 40, windows: { "bottom": (scroll_speed: 20) })`
+pub const WINDOWS_KEY: &str = "windows";
+
+/// The per-window overrides of a user config, the other fields are ignored
+#[derive(Deserialize)]
+struct WindowOverrides<C> {
+    // the name is `WINDOWS_KEY`
+    #[serde(default = "HashMap::new")]
+    windows: HashMap<String, C>,
+}
+
+struct ModuleConfigInner<C> {
+    config: C,
+    windows: HashMap<String, C>,
+    last_error: Option<ConfigError>,
+    watcher: Option<RecommendedWatcher>,
+    window_subscribers: Vec<(ActivityIdentifier, Rc<dyn Fn(&C)>)>,
+}
+
+/// A typed module config loaded from RON
+///
+/// The user config can be partial, every missing field (also in nested structs and maps) is taken from `C::default()`.
+/// The partial config is deserialized directly, so `C` and the structs in it need `#[serde(default)]`.
+///
+/// If the config fails to load the last good config is kept.
+///
+/// Every window can override part of the config in the [`WINDOWS_KEY`] map,
+/// the overrides are merged over the module config, use `get_for_activity` to get the effective config of an activity.
+/// Because of this `C` shouldn't have a field with the same name.
+///
+/// This is cheap to clone, every clone shares the same config,
+/// so it can be stored in the module and read from producers after a reload
+pub struct ModuleConfig<C: DeserializeOwned + Default> {
+    inner: Rc<RefCell<ModuleConfigInner<C>>>,
+}
+
+impl<C: DeserializeOwned + Default> Clone for ModuleConfig<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C: DeserializeOwned + Default> Default for ModuleConfig<C> {
+    fn default() -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(ModuleConfigInner {
+                config: C::default(),
+                windows: HashMap::new(),
+                last_error: None,
+                watcher: None,
+                window_subscribers: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl<C> ModuleConfig<C>
+where
+    C: DeserializeOwned + Default + Serialize + Clone + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a copy of the current config
+    pub fn get(&self) -> C {
+        self.inner.borrow().config.clone()
+    }
+
+    /// Get the error from the last failed update, `None` if the last update succeeded
+    pub fn last_error(&self) -> Option<ConfigError> {
+        self.inner.borrow().last_error.clone()
+    }
+
+    /// Get the effective config for a window
+    ///
+    /// This is the module config if the window doesn't have any override
+    pub fn get_for_window(&self, window_name: Option<&str>) -> C {
+        let inner = self.inner.borrow();
+        Self::resolve_window(&inner.config, &inner.windows, window_name).clone()
+    }
+
+    /// Get the effective config for the window in the metadata of the activity
+    pub fn get_for_activity(&self, activity_id: &ActivityIdentifier) -> C {
+        self.get_for_window(activity_id.metadata().window_name().as_deref())
+    }
+
+    /// Call `callback` with the new effective config every time the config for the window of the activity changes
+    ///
+    /// The callback isn't called if only the config for other windows changed
+    pub fn connect_activity_config_changed<F>(&self, activity_id: ActivityIdentifier, callback: F)
+    where
+        F: Fn(&C) + 'static,
+    {
+        self.inner
+            .borrow_mut()
+            .window_subscribers
+            .push((activity_id, Rc::new(callback)));
+    }
+
+    /// Remove all the callbacks registered for the activity
+    pub fn disconnect_activity_config_changed(&self, activity_id: &ActivityIdentifier) {
+        self.inner
+            .borrow_mut()
+            .window_subscribers
+            .retain(|(id, _)| id != activity_id);
+    }
+
+    /// Merge the (partial) config in `config` over the defaults and replace the current config
+    ///
+    /// returns `Err` and keeps the current config if the string isn't valid RON or if it doesn't match `C`
+    pub fn update_from_str(&self, config: &str) -> Result<()> {
+        let res = (|| {
+            let mask = ron::from_str::<ron::Value>(config)?;
+            let user = ron::from_str::<C>(config)?;
+            let windows = ron::from_str::<WindowOverrides<C>>(config)?.windows;
+            Self::resolve(user, windows, &mask)
+        })();
+        self.apply(res)
+    }
+
+    /// Merge an already parsed (partial) config over the defaults and replace the current config
+    ///
+    /// `ron::Value` doesn't store the enum variants, so the enum fields can only be set with
+    /// [`update_from_str`](Self::update_from_str), here they keep their default value.
+    ///
+    /// returns `Err` and keeps the current config if the value doesn't match `C`
+    pub fn update_from_value(&self, config: ron::Value) -> Result<()> {
+        let res = (|| {
+            let user = config.clone().into_rust::<C>()?;
+            let windows = config.clone().into_rust::<WindowOverrides<C>>()?.windows;
+            Self::resolve(user, windows, &config)
+        })();
+        self.apply(res)
+    }
+
+    /// Watch a standalone config file and reload the config every time it changes
+    ///
+    /// After a successful reload `on_reload` is called with the module and the new config,
+    /// then every producer registered in `base_module` is started again.
+    /// Use `on_reload` to stop the old producers (for example with `ProducerRuntime::shutdown_blocking`)
+    ///
+    /// The file is also loaded immediately if it exists.
+    /// Calling this again replaces the previously watched file
+    pub fn watch_file<T, F>(
+        &self,
+        path: impl AsRef<Path>,
+        base_module: &BaseModule<T>,
+        module: &T,
+        on_reload: F,
+    ) -> Result<()>
+    where
+        T: Clone + 'static,
+        F: Fn(&T, &C) + 'static,
+    {
+        let path = path.as_ref().to_path_buf();
+        if path.is_file() {
+            self.reload_file(&path)
+                .unwrap_or_else(|err| log::error!("{} {:?}: {:#}", base_module.name(), path, err));
+        }
+        let config = self.clone();
+        let base_module = base_module.clone();
+        let module = module.clone();
+        let watched = path.clone();
+        let watcher = watch_path(&path, move || match config.reload_file(&watched) {
+            Ok(()) => {
+                log::debug!("{}: reloaded config from {:?}", base_module.name(), watched);
+                on_reload(&module, &config.get());
+                base_module.run_producers(&module);
+            }
+            Err(err) => {
+                log::error!("{} {:?}: {:#}", base_module.name(), watched, err);
+            }
+        })?;
+        self.inner.borrow_mut().watcher = Some(watcher);
+        Ok(())
+    }
+
+    /// Stop watching the config file
+    pub fn unwatch_file(&self) {
+        self.inner.borrow_mut().watcher = None;
+    }
+
+    fn reload_file(&self, path: &Path) -> Result<()> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {:?}", path))?;
+        self.update_from_str(&content)
+    }
+
+    fn apply(&self, res: std::result::Result<(C, HashMap<String, C>), ConfigError>) -> Result<()> {
+        let (config, windows) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                self.inner.borrow_mut().last_error = Some(err.clone());
+                return Err(anyhow!(err).context("failed to parse config, keeping the last one"));
+            }
+        };
+        let mut inner = self.inner.borrow_mut();
+        let changed: Vec<(Rc<dyn Fn(&C)>, C)> = inner
+            .window_subscribers
+            .iter()
+            .filter_map(|(id, callback)| {
+                let window_name = id.metadata().window_name();
+                let old =
+                    Self::resolve_window(&inner.config, &inner.windows, window_name.as_deref());
+                let new = Self::resolve_window(&config, &windows, window_name.as_deref());
+                // `C` doesn't need to implement PartialEq, compare the serialized values instead
+                if ron::to_string(old).ok() == ron::to_string(new).ok() {
+                    None
+                } else {
+                    Some((callback.clone(), new.clone()))
+                }
+            })
+            .collect();
+        inner.config = config;
+        inner.windows = windows;
+        inner.last_error = None;
+        drop(inner);
+        // the callbacks can read the config, so the borrow needs to be dropped first
+        for (callback, config) in changed {
+            callback(&config);
+        }
+        Ok(())
+    }
+
+    fn resolve_window<'a>(
+        config: &'a C,
+        windows: &'a HashMap<String, C>,
+        window_name: Option<&str>,
+    ) -> &'a C {
+        window_name
+            .and_then(|window_name| windows.get(window_name))
+            .unwrap_or(config)
+    }
+
+    /// Merge the user config over the defaults, then merge every window override over the result
+    ///
+    /// `user` and `windows` are deserialized from the user config and `mask` is the same config as a `ron::Value`,
+    /// only the fields in `mask` are taken from the user config
+    fn resolve(
+        user: C,
+        windows: HashMap<String, C>,
+        mask: &ron::Value,
+    ) -> std::result::Result<(C, HashMap<String, C>), ConfigError> {
+        let merged = ConfigTree::new(&C::default())?.merge(ConfigTree::new(&user)?, mask);
+        let config = merged.parse::<C>()?;
+
+        let window_masks = match mask {
+            ron::Value::Map(map) => map
+                .iter()
+                .find(|(key, _)| **key == ron::Value::String(WINDOWS_KEY.to_string()))
+                .map(|(_, windows)| windows),
+            _ => None,
+        };
+        let mut window_configs = HashMap::new();
+        for (window_name, window_config) in windows {
+            // without a mask the override replaces the whole config
+            let window_mask = match window_masks {
+                Some(ron::Value::Map(masks)) => masks
+                    .iter()
+                    .find(|(key, _)| **key == ron::Value::String(window_name.clone()))
+                    .map(|(_, mask)| mask),
+                _ => None,
+            }
+            .unwrap_or(&ron::Value::Unit);
+            let window_config = merged
+                .clone()
+                .merge(ConfigTree::new(&window_config)?, window_mask)
+                .parse::<C>()
+                .map_err(|err| ConfigError {
+                    position: None,
+                    message: format!("{WINDOWS_KEY}.{window_name}: {err}"),
+                })?;
+            window_configs.insert(window_name, window_config);
+        }
+        Ok((config, window_configs))
+    }
+}
+
+/// Call `on_change` on the glib main context every time the file at `path` is created or modified
+///
+/// The parent dir is watched because editors usually replace the file instead of writing to it,
+/// so the file doesn't need to exist yet. The file stops being watched when the returned watcher is dropped
+pub(crate) fn watch_path<F>(path: &Path, on_change: F) -> Result<RecommendedWatcher>
+where
+    F: Fn() + 'static,
+{
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize()
+        .with_context(|| format!("failed to find the parent dir of {:?}", path))?;
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{:?} is not a file", path))?;
+    // the events contain the path of the watched dir, relative paths (like `./` or `..`) wouldn't match
+    let watched = dir.join(file_name);
+
+    let (change_send, mut change_recv) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) => {
+                if (event.kind.is_modify() || event.kind.is_create())
+                    && event.paths.iter().any(|p| *p == watched)
+                {
+                    let _ = change_send.send(());
+                }
+            }
+            Err(err) => log::warn!("file watcher error: {err}"),
+        })
+        .with_context(|| "failed to create file watcher")?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("failed to watch {:?}", dir))?;
+
+    glib::MainContext::default().spawn_local(async move {
+        // the loop ends when the watcher (and the sender) is dropped
+        while change_recv.recv().await.is_some() {
+            // an editor save usually generates more than one event
+            while change_recv.try_recv().is_ok() {}
+            on_change();
+        }
+    });
+    Ok(watcher)
+}
+
+/// Recursively merge `over` into `base`
+///
+/// Maps (and structs) are merged key by key, every other value in `over` replaces the one in `base`.
+///
+/// `ron::Value` doesn't store the enum variants, a merged value with an enum can't be deserialized back,
+/// [`ModuleConfig`] merges the typed configs instead
+pub fn merge_ron_values(base: &mut ron::Value, over: ron::Value) {
+    match (base, over) {
+        (ron::Value::Map(base_map), ron::Value::Map(over_map)) => {
+            for (key, value) in over_map {
+                match base_map.remove(&key) {
+                    Some(mut base_value) => {
+                        merge_ron_values(&mut base_value, value);
+                        base_map.insert(key, base_value);
+                    }
+                    None => {
+                        base_map.insert(key, value);
+                    }
+                }
+            }
+        }
+        (ron::Value::Option(Some(base_value)), ron::Value::Option(Some(value))) => {
+            merge_ron_values(base_value, *value);
+        }
+        (base, over) => {
+            *base = over;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::{merge_ron_values, ModuleConfig};
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    enum Position {
+        Top,
+        #[default]
+        Bottom,
+        Offset(i32),
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct TestConfig {
+        speed: i32,
+        position: Position,
+        label: Option<LabelConfig>,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                speed: 10,
+                position: Position::Offset(4),
+                label: Some(LabelConfig::default()),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct LabelConfig {
+        text: String,
+        position: Position,
+    }
+
+    impl Default for LabelConfig {
+        fn default() -> Self {
+            Self {
+                text: "label".to_string(),
+                position: Position::Top,
+            }
+        }
+    }
+
+    fn value(source: &str) -> ron::Value {
+        ron::from_str(source).unwrap()
+    }
+
+    fn merged(base: &str, over: &str) -> ron::Value {
+        let mut base = value(base);
+        merge_ron_values(&mut base, value(over));
+        base
+    }
+
+    #[test]
+    fn nested_maps_are_merged_by_key() {
+        assert_eq!(
+            merged(
+                r#"{"a": 1, "nested": {"b": 2, "c": 3}}"#,
+                r#"{"nested": {"c": 4, "d": 5}}"#
+            ),
+            value(r#"{"a": 1, "nested": {"b": 2, "c": 4, "d": 5}}"#)
+        );
+    }
+
+    #[test]
+    fn structs_are_merged_like_maps() {
+        assert_eq!(
+            merged("(a: 1, inner: (b: 2, c: 3))", "(inner: (b: 20))"),
+            value("(a: 1, inner: (b: 20, c: 3))")
+        );
+    }
+
+    #[test]
+    fn scalars_are_replaced() {
+        assert_eq!(
+            merged(r#"{"a": 1, "b": "old"}"#, r#"{"a": 2.5, "b": "new"}"#),
+            value(r#"{"a": 2.5, "b": "new"}"#)
+        );
+        assert_eq!(merged("true", "false"), value("false"));
+    }
+
+    #[test]
+    fn sequences_are_replaced_not_merged() {
+        assert_eq!(
+            merged(r#"{"list": [1, 2, 3]}"#, r#"{"list": [4]}"#),
+            value(r#"{"list": [4]}"#)
+        );
+    }
+
+    #[test]
+    fn options_are_merged_when_both_are_some() {
+        assert_eq!(
+            merged(r#"Some({"a": 1, "b": 2})"#, r#"Some({"b": 3})"#),
+            value(r#"Some({"a": 1, "b": 3})"#)
+        );
+        assert_eq!(merged(r#"Some({"a": 1})"#, "None"), value("None"));
+    }
+
+    #[test]
+    fn resolve_keeps_the_default_enum_variants() {
+        let config = ModuleConfig::<TestConfig>::new();
+        config.update_from_str("()").unwrap();
+        assert_eq!(config.get(), TestConfig::default());
+        config
+            .update_from_value(ron::from_str("(speed: 20)").unwrap())
+            .unwrap();
+        assert_eq!(config.get().speed, 20);
+        assert_eq!(config.get().position, Position::Offset(4));
+    }
+
+    #[test]
+    fn resolve_merges_enum_fields() {
+        let config = ModuleConfig::<TestConfig>::new();
+        config
+            .update_from_str(
+                r#"(
+                    position: Top,
+                    label: Some((position: Offset(2))),
+                    windows: {"bottom": (speed: 3, label: Some((text: "bottom")))},
+                )"#,
+            )
+            .unwrap();
+        let expected = TestConfig {
+            speed: 10,
+            position: Position::Top,
+            label: Some(LabelConfig {
+                text: "label".to_string(),
+                position: Position::Offset(2),
+            }),
+        };
+        assert_eq!(config.get(), expected);
+        assert_eq!(
+            config.get_for_window(Some("bottom")),
+            TestConfig {
+                speed: 3,
+                label: Some(LabelConfig {
+                    text: "bottom".to_string(),
+                    position: Position::Offset(2),
+                }),
+                ..expected.clone()
+            }
+        );
+        assert_eq!(config.get_for_window(Some("top")), expected);
+    }
+
+    #[test]
+    fn invalid_config_keeps_the_last_one() {
+        let config = ModuleConfig::<TestConfig>::new();
+        config.update_from_str("(position: Top)").unwrap();
+        assert!(config.update_from_str("(\n    speed: \"fast\")").is_err());
+        assert_eq!(config.last_error().unwrap().position, Some((2, 12)));
+        assert_eq!(config.get().position, Position::Top);
+    }
+}
@@ -0,0 +1,613 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use abi::log::{self, Level, LevelFilter, Log, Metadata, Record};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The environment variable with the log level of each module
+///
+/// example: `DYNISLAND_LOG=music=debug,clock=warn`, an entry without a module name (`DYNISLAND_LOG=info`) applies to every module
+pub const LOG_ENV_VAR: &str = "DYNISLAND_LOG";
+
+/// How often the summaries of the suppressed messages are written if no other message arrives
+const SUMMARY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Logging config of a module
+///
+/// It can be embedded in the module config, every field is optional
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// The log level of the module (`off`, `error`, `warn`, `info`, `debug`, `trace`),
+    /// overridden by [`LOG_ENV_VAR`]
+    pub level: String,
+    /// A file where the logs are also written
+    pub file: Option<PathBuf>,
+    /// The size in bytes after which the log file is rotated
+    pub max_file_size: u64,
+    /// How many rotated log files to keep (`file.1`, `file.2`, ...)
+    pub max_files: usize,
+    /// The time in milliseconds during which identical messages are suppressed, 0 to disable
+    pub rate_limit: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            file: None,
+            max_file_size: 1024 * 1024,
+            max_files: 3,
+            rate_limit: 1000,
+        }
+    }
+}
+
+/// Initialize the logger for the module, every record with a target in `target` is tagged with `module_name`
+///
+/// `target` is usually the name of the crate of the module (`env!("CARGO_CRATE_NAME")`),
+/// the records of a target inside it (like `music_module::player`) are also handled by this module.
+/// The records of other targets are written to stderr without a tag, with the level of the global entry of [`LOG_ENV_VAR`]
+///
+/// The module level is taken from [`LOG_ENV_VAR`] if it contains an entry for this module, otherwise from `config.level`.
+///
+/// Every module in the process has its own level, file and rate limit.
+/// This can be called again (for example after a config reload) to replace the config of the module
+///
+/// returns `Err` if the level isn't valid, if the log file can't be opened, or if another logger was already set.
+/// In the last case every following call also fails
+pub fn init(module_name: &str, target: &str, config: &LogConfig) -> Result<()> {
+    let env_spec = std::env::var(LOG_ENV_VAR).unwrap_or_default();
+    let level = match module_level(&env_spec, module_name) {
+        Some(level) => level,
+        None => LevelFilter::from_str(&config.level)
+            .map_err(|_| anyhow!("invalid log level {:?}", config.level))?,
+    };
+    let state = LoggerState::new(module_name, target, level, config)?;
+
+    static LOGGER: OnceLock<ModuleLogger> = OnceLock::new();
+    /// Whether `LOGGER` is the global logger, it's only set after `set_logger` succeeds
+    static INSTALLED: Mutex<bool> = Mutex::new(false);
+    let logger = LOGGER.get_or_init(|| ModuleLogger::new(&env_spec));
+    let mut installed = INSTALLED.lock().unwrap();
+    if !*installed {
+        log::set_logger(logger).with_context(|| "another logger was already set")?;
+        *installed = true;
+        // the summaries of the suppressed messages are also written when no other message arrives
+        std::thread::Builder::new()
+            .name("module-logger".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(SUMMARY_CHECK_INTERVAL);
+                logger.write_summaries(false);
+            })
+            .with_context(|| "failed to start the logger thread")?;
+    }
+    let mut modules = logger.modules.lock().unwrap();
+    // the summary of the replaced state is written when it's dropped
+    modules.insert(module_name.to_string(), state);
+    let max_level = modules
+        .values()
+        .map(|state| state.level)
+        .fold(logger.fallback.filter(), LevelFilter::max);
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Parse the level for `module_name` from a spec like `music=debug,clock=warn,info`
+///
+/// The entry with the module name has the priority over the global one
+pub fn module_level(spec: &str, module_name: &str) -> Option<LevelFilter> {
+    module_level_entry(spec, Some(module_name))
+}
+
+/// Parse the level of the entry without a module name from a spec like `music=debug,info`
+fn global_level(spec: &str) -> Option<LevelFilter> {
+    module_level_entry(spec, None)
+}
+
+fn module_level_entry(spec: &str, module_name: Option<&str>) -> Option<LevelFilter> {
+    let mut global = None;
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once('=') {
+            Some((name, level)) => {
+                if Some(name.trim()) == module_name {
+                    if let Ok(level) = LevelFilter::from_str(level.trim()) {
+                        return Some(level);
+                    }
+                }
+            }
+            None => {
+                if let Ok(level) = LevelFilter::from_str(entry) {
+                    global = Some(level);
+                }
+            }
+        }
+    }
+    global
+}
+
+struct ModuleLogger {
+    /// The state of every module, by module name
+    modules: Mutex<HashMap<String, LoggerState>>,
+    /// Writes the records that don't belong to a module
+    fallback: env_logger::Logger,
+}
+
+impl ModuleLogger {
+    fn new(env_spec: &str) -> Self {
+        let level = global_level(env_spec).unwrap_or(LevelFilter::Info);
+        Self {
+            modules: Mutex::new(HashMap::new()),
+            fallback: env_logger::Builder::new().filter_level(level).build(),
+        }
+    }
+
+    /// Write the summaries of the suppressed messages, only the ones whose interval ended if `all` is `false`
+    fn write_summaries(&self, all: bool) {
+        for state in self.modules.lock().unwrap().values_mut() {
+            state.write_summary(all);
+        }
+    }
+}
+
+/// The module whose target contains `target`, the most specific one if more than one does
+fn module_for<'a>(
+    modules: &'a mut HashMap<String, LoggerState>,
+    target: &str,
+) -> Option<&'a mut LoggerState> {
+    modules
+        .values_mut()
+        .filter(|state| {
+            target == state.target
+                || target
+                    .strip_prefix(state.target.as_str())
+                    .is_some_and(|rest| rest.starts_with("::"))
+        })
+        .max_by_key(|state| state.target.len())
+}
+
+impl Log for ModuleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match module_for(&mut self.modules.lock().unwrap(), metadata.target()) {
+            Some(state) => metadata.level() <= state.level,
+            None => self.fallback.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        let mut modules = self.modules.lock().unwrap();
+        let Some(state) = module_for(&mut modules, record.target()) else {
+            drop(modules);
+            self.fallback.log(record);
+            return;
+        };
+        if record.level() > state.level {
+            return;
+        }
+        let message = record.args().to_string();
+        if let Some(summary) = state.limiter.check(record, &message) {
+            state.write(record, &summary);
+        }
+        if state.limiter.suppressed(record, &message) {
+            return;
+        }
+        state.write(record, &message);
+    }
+
+    fn flush(&self) {
+        self.write_summaries(true);
+        for state in self.modules.lock().unwrap().values_mut() {
+            if let Some(file) = &mut state.file {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+struct LoggerState {
+    module_name: String,
+    /// The prefix of the targets of the module records
+    target: String,
+    level: LevelFilter,
+    stderr: env_logger::Logger,
+    file: Option<RotatingFile>,
+    limiter: RateLimiter,
+}
+
+impl LoggerState {
+    fn new(
+        module_name: &str,
+        target: &str,
+        level: LevelFilter,
+        config: &LogConfig,
+    ) -> Result<Self> {
+        let file = match &config.file {
+            Some(path) => Some(RotatingFile::open(
+                path,
+                config.max_file_size,
+                config.max_files,
+            )?),
+            None => None,
+        };
+        let tag = module_name.to_string();
+        let stderr = env_logger::Builder::new()
+            .filter_level(level)
+            .format(move |buf, record| {
+                writeln!(
+                    buf,
+                    "[{} {} {} {}] {}",
+                    buf.timestamp(),
+                    record.level(),
+                    tag,
+                    record.target(),
+                    record.args()
+                )
+            })
+            .build();
+        Ok(Self {
+            module_name: module_name.to_string(),
+            target: target.to_string(),
+            level,
+            stderr,
+            file,
+            limiter: RateLimiter::new(Duration::from_millis(config.rate_limit)),
+        })
+    }
+
+    fn write(&mut self, record: &Record, message: &str) {
+        self.stderr.log(
+            &Record::builder()
+                .args(format_args!("{message}"))
+                .level(record.level())
+                .target(record.target())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+        let Some(file) = &mut self.file else {
+            return;
+        };
+        let line = format!(
+            "[{} {} {}] {}",
+            record.level(),
+            self.module_name,
+            record.target(),
+            message
+        );
+        if let Err(err) = file.write_line(&line) {
+            // the logger can't log through the `log` macros while it's locked
+            self.stderr.log(
+                &Record::builder()
+                    .args(format_args!("failed to write the log file: {err:#}"))
+                    .level(Level::Error)
+                    .target(module_path!())
+                    .build(),
+            );
+        }
+    }
+
+    /// Write the summary of the suppressed messages, if `all` is `false` only if their interval ended
+    fn write_summary(&mut self, all: bool) {
+        if let Some((level, target, summary)) = self.limiter.summary(all) {
+            self.write(
+                &Record::builder()
+                    .args(format_args!("{summary}"))
+                    .level(level)
+                    .target(&target)
+                    .build(),
+                &summary,
+            );
+        }
+    }
+}
+
+impl Drop for LoggerState {
+    fn drop(&mut self) {
+        self.write_summary(true);
+    }
+}
+
+/// Drops identical messages logged within `interval` of the first one
+struct RateLimiter {
+    interval: Duration,
+    /// The key, level and target of the last message and when it was first logged
+    last: Option<(String, Level, String, Instant)>,
+    suppressed: u32,
+}
+
+impl RateLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+            suppressed: 0,
+        }
+    }
+
+    /// returns a summary of the suppressed messages if a different message arrives or if the interval ended
+    fn check(&mut self, record: &Record, message: &str) -> Option<String> {
+        if self.interval.is_zero() || self.suppressed == 0 {
+            return None;
+        }
+        let key = Self::key(record, message);
+        let (last_key, _, _, since) = self.last.as_ref()?;
+        if *last_key == key && since.elapsed() < self.interval {
+            return None;
+        }
+        self.summary(true).map(|(_, _, summary)| summary)
+    }
+
+    /// returns the level, the target and the summary of the suppressed messages,
+    /// if `all` is `false` only if the interval ended.
+    /// The count is reset
+    fn summary(&mut self, all: bool) -> Option<(Level, String, String)> {
+        if self.suppressed == 0 {
+            return None;
+        }
+        let (key, level, target, since) = self.last.as_ref()?;
+        if !all && since.elapsed() < self.interval {
+            return None;
+        }
+        let summary = format!(
+            "previous message repeated {} more times: {}",
+            self.suppressed, key
+        );
+        let res = (*level, target.clone(), summary);
+        self.suppressed = 0;
+        self.last = None;
+        Some(res)
+    }
+
+    /// returns `true` if the message should be dropped
+    fn suppressed(&mut self, record: &Record, message: &str) -> bool {
+        if self.interval.is_zero() {
+            return false;
+        }
+        let key = Self::key(record, message);
+        match &self.last {
+            Some((last_key, _, _, since))
+                if *last_key == key && since.elapsed() < self.interval =>
+            {
+                self.suppressed += 1;
+                true
+            }
+            _ => {
+                self.last = Some((
+                    key,
+                    record.level(),
+                    record.target().to_string(),
+                    Instant::now(),
+                ));
+                false
+            }
+        }
+    }
+
+    fn key(record: &Record, message: &str) -> String {
+        format!("{} {}: {}", record.level(), record.target(), message)
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create log directory {:?}", parent))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open log file {:?}", path))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    /// Write `line`, rotating the file first if it would grow past `max_size`
+    ///
+    /// returns `Err` if the rotation or the write failed, the line is still written after a failed rotation
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let rotated = if self.max_size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()
+                .with_context(|| format!("failed to rotate log file {:?}", self.path))
+        } else {
+            Ok(())
+        };
+        writeln!(self.file, "{line}")
+            .with_context(|| format!("failed to write to log file {:?}", self.path))?;
+        self.size += line.len() as u64 + 1;
+        rotated
+    }
+
+    /// `file` -> `file.1` -> `file.2` ..., the oldest one is removed
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            let _ = std::fs::remove_file(rotated(self.max_files));
+            for n in (1..self.max_files).rev() {
+                let _ = std::fs::rename(rotated(n), rotated(n + 1));
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use abi::log::{Level, LevelFilter, Record};
+
+    use super::{module_for, module_level, LogConfig, LoggerState, RateLimiter};
+
+    fn with_record<R>(level: Level, f: impl FnOnce(&Record) -> R) -> R {
+        f(&Record::builder()
+            .args(format_args!("message"))
+            .level(level)
+            .target("test")
+            .build())
+    }
+
+    #[test]
+    fn module_entry_has_priority_over_global() {
+        let spec = "info,music=debug,clock=warn";
+        assert_eq!(module_level(spec, "music"), Some(LevelFilter::Debug));
+        assert_eq!(module_level(spec, "clock"), Some(LevelFilter::Warn));
+        assert_eq!(module_level(spec, "other"), Some(LevelFilter::Info));
+        assert_eq!(
+            module_level("music=trace,error", "music"),
+            Some(LevelFilter::Trace)
+        );
+    }
+
+    #[test]
+    fn module_level_ignores_invalid_and_missing_entries() {
+        assert_eq!(module_level("", "music"), None);
+        assert_eq!(module_level("clock=debug", "music"), None);
+        assert_eq!(module_level("music=loud", "music"), None);
+        assert_eq!(
+            module_level(" music = off , ,", "music"),
+            Some(LevelFilter::Off)
+        );
+    }
+
+    #[test]
+    fn identical_messages_are_suppressed() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        with_record(Level::Warn, |record| {
+            assert!(!limiter.suppressed(record, "disconnected"));
+            assert!(limiter.suppressed(record, "disconnected"));
+            assert!(limiter.suppressed(record, "disconnected"));
+            // the same message is still suppressed, nothing to summarize yet
+            assert_eq!(limiter.check(record, "disconnected"), None);
+        });
+        // a different level is a different message
+        with_record(Level::Error, |record| {
+            assert!(!limiter.suppressed(record, "disconnected"));
+        });
+    }
+
+    #[test]
+    fn different_message_reports_suppressed_count() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        with_record(Level::Info, |record| {
+            limiter.suppressed(record, "a");
+            limiter.suppressed(record, "a");
+            limiter.suppressed(record, "a");
+            assert_eq!(
+                limiter.check(record, "b"),
+                Some("previous message repeated 2 more times: INFO test: a".to_string())
+            );
+            assert!(!limiter.suppressed(record, "b"));
+            // the count was reset
+            assert_eq!(limiter.check(record, "c"), None);
+        });
+    }
+
+    #[test]
+    fn messages_pass_again_after_the_interval() {
+        let mut limiter = RateLimiter::new(Duration::from_millis(10));
+        with_record(Level::Info, |record| {
+            assert!(!limiter.suppressed(record, "a"));
+            assert!(limiter.suppressed(record, "a"));
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(limiter.check(record, "a").is_some());
+            assert!(!limiter.suppressed(record, "a"));
+        });
+    }
+
+    #[test]
+    fn summary_is_written_after_the_interval_without_other_messages() {
+        let mut limiter = RateLimiter::new(Duration::from_millis(10));
+        with_record(Level::Warn, |record| {
+            limiter.suppressed(record, "a");
+            limiter.suppressed(record, "a");
+            assert_eq!(limiter.summary(false), None);
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(
+                limiter.summary(false),
+                Some((
+                    Level::Warn,
+                    "test".to_string(),
+                    "previous message repeated 1 more times: WARN test: a".to_string()
+                ))
+            );
+            assert_eq!(limiter.summary(true), None);
+            // when the module logger is replaced the summary is written before the interval ends
+            limiter.suppressed(record, "b");
+            limiter.suppressed(record, "b");
+            assert!(limiter.summary(true).is_some());
+        });
+    }
+
+    #[test]
+    fn records_go_to_the_module_of_their_target() {
+        let mut modules = HashMap::new();
+        for (name, target) in [
+            ("music", "music_module"),
+            ("player", "music_module::player"),
+        ] {
+            let state =
+                LoggerState::new(name, target, LevelFilter::Info, &LogConfig::default()).unwrap();
+            modules.insert(name.to_string(), state);
+        }
+        let module = |modules: &mut HashMap<String, LoggerState>, target: &str| {
+            module_for(modules, target).map(|state| state.module_name.clone())
+        };
+        assert_eq!(
+            module(&mut modules, "music_module").as_deref(),
+            Some("music")
+        );
+        assert_eq!(
+            module(&mut modules, "music_module::ui").as_deref(),
+            Some("music")
+        );
+        assert_eq!(
+            module(&mut modules, "music_module::player::mpris").as_deref(),
+            Some("player")
+        );
+        assert_eq!(module(&mut modules, "music_module_extra"), None);
+        assert_eq!(module(&mut modules, "dynisland_core::base_module"), None);
+    }
+
+    #[test]
+    fn zero_interval_disables_the_limiter() {
+        let mut limiter = RateLimiter::new(Duration::ZERO);
+        with_record(Level::Info, |record| {
+            assert!(!limiter.suppressed(record, "a"));
+            assert!(!limiter.suppressed(record, "a"));
+            assert_eq!(limiter.check(record, "a"), None);
+        });
+    }
+}
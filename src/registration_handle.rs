@@ -0,0 +1,90 @@
+use abi::{glib, log, module::ActivityIdentifier};
+use anyhow::{anyhow, Result};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{base_module::BaseModule, dynamic_activity::DynamicActivity};
+
+type Request<T> = Box<dyn FnOnce(&BaseModule<T>) + Send>;
+
+/// A `Send` handle to register and unregister activities from outside the glib main context,
+/// for example from the producers running on the `ProducerRuntime`
+///
+/// Every request is executed on the glib main context with the `BaseModule` that created the handle.
+///
+/// The `DynamicActivity` can't be sent between threads, so `register` takes a closure that builds it on the main context.
+///
+/// The handle keeps the module alive until every clone is dropped
+pub struct RegistrationHandle<T> {
+    req_send: UnboundedSender<Request<T>>,
+}
+
+impl<T> Clone for RegistrationHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            req_send: self.req_send.clone(),
+        }
+    }
+}
+
+impl<T: 'static> RegistrationHandle<T> {
+    pub(crate) fn new(base_module: BaseModule<T>) -> Self {
+        let (req_send, mut req_recv) = tokio::sync::mpsc::unbounded_channel::<Request<T>>();
+        glib::MainContext::default().spawn_local(async move {
+            while let Some(request) = req_recv.recv().await {
+                request(&base_module);
+            }
+            log::trace!("{}: registration handle dropped", base_module.name());
+        });
+        Self { req_send }
+    }
+
+    /// Run a closure with the `BaseModule` on the glib main context and get its result
+    ///
+    /// returns `Err` if the main context isn't running anymore
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&BaseModule<T>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (res_send, res_recv) = tokio::sync::oneshot::channel();
+        self.req_send
+            .send(Box::new(move |base_module| {
+                let _ = res_send.send(f(base_module));
+            }))
+            .map_err(|_| anyhow!("the module main context isn't running"))?;
+        res_recv
+            .await
+            .map_err(|_| anyhow!("the module main context dropped the request"))
+    }
+
+    /// Build an activity on the glib main context and register it with the app
+    ///
+    /// returns the identifier of the new activity, or `Err` if the activity was already registered
+    pub async fn register<F>(&self, build_activity: F) -> Result<ActivityIdentifier>
+    where
+        F: FnOnce(&BaseModule<T>) -> DynamicActivity + Send + 'static,
+    {
+        self.run(move |base_module| {
+            let activity = build_activity(base_module);
+            let id = activity.get_identifier();
+            base_module.register_activity(activity).map(|_| id)
+        })
+        .await?
+    }
+
+    /// Unregister the activity with this identifier, the metadata has to match too
+    ///
+    /// does nothing if the activity wasn't registered
+    pub async fn unregister(&self, activity_id: ActivityIdentifier) -> Result<()> {
+        self.run(move |base_module| base_module.unregister_by_id(&activity_id))
+            .await
+    }
+
+    /// Unregister every activity with that name in the identifier
+    ///
+    /// does nothing if the activity wasn't registered
+    pub async fn unregister_by_name(&self, activity_name: String) -> Result<()> {
+        self.run(move |base_module| base_module.unregister_activity(&activity_name))
+            .await
+    }
+}
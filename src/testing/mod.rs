@@ -0,0 +1,383 @@
+//! Helpers to test modules without the dynisland app
+//!
+//! Enabled with the `testing` feature.
+//!
+//! GTK can only be used from the thread that initialized it,
+//! so the tests using these helpers should run with `--test-threads=1`
+//!
+//! # Example
+//! ```ignore
+//! #[test]
+//! fn shows_the_activity() {
+//!     dynisland_core::testing::init_gtk().unwrap();
+//!     let host = FakeHost::new();
+//!     let base_module = host.base_module::<()>("ExampleModule");
+//!     // ... register the activities and update the properties
+//!     host.pump();
+//!     host.assert_registered("example-activity");
+//!     assert_property(&base_module, "example-activity", "label", String::from("hello"));
+//!     assert_mode(&base_module, "example-activity", ActivityMode::Expanded);
+//! }
+//! ```
+//!
+//! [`snapshot`] renders widgets headlessly for golden-image tests
+pub mod snapshot;
+
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use abi::{abi_stable, glib, gtk, log, module::ActivityIdentifier};
+use abi_stable::external_types::crossbeam_channel::{self, RReceiver, RSender};
+use anyhow::{anyhow, Result};
+use dynisland_abi::module::UIServerCommand;
+
+use crate::{
+    base_module::BaseModule, dynamic_property::ValidDynType,
+    graphics::activity_widget::boxed_activity_mode::ActivityMode,
+};
+
+/// How many main context iterations `pump` runs at most before giving up
+const MAX_PUMP_ITERATIONS: usize = 10_000;
+
+/// Initialize GTK for the tests, can be called multiple times from the same thread
+///
+/// returns `Err` if GTK can't be initialized (for example if there is no display)
+pub fn init_gtk() -> Result<()> {
+    if gtk::is_initialized_main_thread() {
+        return Ok(());
+    }
+    gtk::init().map_err(|err| anyhow!("failed to initialize gtk: {err}"))
+}
+
+/// A fake dynisland app that captures every `UIServerCommand` sent by the modules
+pub struct FakeHost {
+    app_send: RSender<UIServerCommand>,
+    app_recv: RReceiver<UIServerCommand>,
+    commands: RefCell<Vec<UIServerCommand>>,
+    registered: RefCell<Vec<ActivityIdentifier>>,
+}
+
+impl Default for FakeHost {
+    fn default() -> Self {
+        let (app_send, app_recv) = crossbeam_channel::unbounded();
+        Self {
+            app_send,
+            app_recv,
+            commands: RefCell::new(Vec::new()),
+            registered: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl FakeHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the channel to pass to `BaseModule::new`
+    pub fn app_send(&self) -> RSender<UIServerCommand> {
+        self.app_send.clone()
+    }
+
+    /// Create a `BaseModule` connected to this host
+    pub fn base_module<T>(&self, name: &'static str) -> BaseModule<T> {
+        BaseModule::new(name, self.app_send())
+    }
+
+    /// Run the glib main context until there is nothing left to do,
+    /// this delivers the property updates sent from the main thread and the commands sent to the host
+    ///
+    /// Timers that didn't fire yet are not waited for, use `pump_for` for those
+    pub fn pump(&self) {
+        let context = glib::MainContext::default();
+        let mut iterations = 0;
+        while context.pending() && iterations < MAX_PUMP_ITERATIONS {
+            context.iteration(false);
+            iterations += 1;
+        }
+        if iterations == MAX_PUMP_ITERATIONS {
+            log::warn!(
+                "the main context still has pending events after {MAX_PUMP_ITERATIONS} iterations"
+            );
+        }
+        self.collect_commands();
+    }
+
+    /// Run the glib main context for `duration`, used to wait for timers
+    /// and for the property updates sent from the producer runtime
+    pub fn pump_for(&self, duration: Duration) {
+        let context = glib::MainContext::default();
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            if !context.iteration(false) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        self.pump();
+    }
+
+    /// Run the glib main context until `condition` returns `true`
+    ///
+    /// returns `Err` if `timeout` runs out first
+    pub fn pump_until<F>(&self, timeout: Duration, mut condition: F) -> Result<()>
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let context = glib::MainContext::default();
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.pump();
+            if condition(self) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!("condition not met after {timeout:?}"));
+            }
+            if !context.iteration(false) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// Remove and return every command received until now
+    pub fn take_commands(&self) -> Vec<UIServerCommand> {
+        self.collect_commands();
+        std::mem::take(&mut *self.commands.borrow_mut())
+    }
+
+    /// Get the activities the app would be showing now, in the order they were added
+    pub fn registered_activities(&self) -> Vec<ActivityIdentifier> {
+        self.collect_commands();
+        self.registered.borrow().clone()
+    }
+
+    /// Check if the app would be showing an activity with this name (activity_identifier.activity())
+    pub fn is_registered(&self, activity_name: &str) -> bool {
+        self.registered_activities()
+            .iter()
+            .any(|id| id.activity() == activity_name)
+    }
+
+    /// Panics if no activity with this name was added to the app
+    #[track_caller]
+    pub fn assert_registered(&self, activity_name: &str) {
+        assert!(
+            self.is_registered(activity_name),
+            "activity {activity_name} isn't registered, registered activities: {:?}",
+            self.registered_names()
+        );
+    }
+
+    /// Panics if an activity with this name is still added to the app
+    #[track_caller]
+    pub fn assert_unregistered(&self, activity_name: &str) {
+        assert!(
+            !self.is_registered(activity_name),
+            "activity {activity_name} is still registered"
+        );
+    }
+
+    fn registered_names(&self) -> Vec<String> {
+        self.registered
+            .borrow()
+            .iter()
+            .map(|id| id.activity().to_string())
+            .collect()
+    }
+
+    fn collect_commands(&self) {
+        while let Ok(command) = self.app_recv.try_recv() {
+            let mut registered = self.registered.borrow_mut();
+            match &command {
+                UIServerCommand::AddActivity { activity_id, .. } => {
+                    registered.push(activity_id.clone());
+                }
+                UIServerCommand::RemoveActivity { activity_id } => {
+                    registered.retain(|id| id != activity_id);
+                }
+                #[allow(unreachable_patterns)]
+                _ => {}
+            }
+            self.commands.borrow_mut().push(command);
+        }
+    }
+}
+
+/// Panics if the property doesn't exist, has a different type or has a different value
+///
+/// blocking
+///
+/// # Arguments
+/// * `activity_name` - The name of the activity (activity_identifier.activity())
+/// * `property_name` - The name of the property
+/// * `expected` - The expected value
+#[track_caller]
+pub fn assert_property<T, V>(
+    base_module: &BaseModule<T>,
+    activity_name: &str,
+    property_name: &str,
+    expected: V,
+) where
+    V: ValidDynType + PartialEq + Debug,
+{
+    let property = base_module
+        .registered_activities()
+        .blocking_lock()
+        .get_property_any_blocking(activity_name, property_name)
+        .unwrap_or_else(|err| panic!("{err}"));
+    let property = property.blocking_lock();
+    match property.get().as_any().downcast_ref::<V>() {
+        Some(value) => assert_eq!(
+            value, &expected,
+            "property {property_name} of {activity_name} has the wrong value"
+        ),
+        None => panic!(
+            "property {property_name} of {activity_name} isn't a {}",
+            std::any::type_name::<V>()
+        ),
+    }
+}
+
+/// Panics if the activity isn't in the activity map or if its widget is in a different mode
+///
+/// blocking
+///
+/// # Arguments
+/// * `activity_name` - The name of the activity (activity_identifier.activity())
+/// * `expected` - The expected mode
+#[track_caller]
+pub fn assert_mode<T>(base_module: &BaseModule<T>, activity_name: &str, expected: ActivityMode) {
+    let activity = base_module
+        .registered_activities()
+        .blocking_lock()
+        .get_activity(activity_name)
+        .unwrap_or_else(|err| panic!("{err}"));
+    let mode = activity.blocking_lock().get_activity_widget().mode();
+    assert_eq!(
+        mode, expected,
+        "activity {activity_name} is in the wrong mode"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use abi::gtk;
+    use tokio::sync::Mutex;
+
+    use super::{assert_mode, assert_property, init_gtk, FakeHost};
+    use crate::{
+        base_module::BaseModule, dynamic_activity::DynamicActivity,
+        graphics::activity_widget::boxed_activity_mode::ActivityMode,
+    };
+
+    const MODULE: &str = "TestModule";
+
+    fn activity(base_module: &BaseModule<()>, name: &str) -> DynamicActivity {
+        let mut activity = DynamicActivity::new(base_module.prop_send(), MODULE, name);
+        activity
+            .add_dynamic_property("label", String::from("initial"))
+            .unwrap();
+        let widget = activity.get_activity_widget();
+        widget.set_minimal_mode_widget(gtk::Label::new(None));
+        widget.set_compact_mode_widget(gtk::Label::new(None));
+        activity
+    }
+
+    /// GTK can only be used from one thread, so the tests need a display and `--test-threads=1`
+    fn init() {
+        if let Err(err) = init_gtk() {
+            panic!(
+                "{err:#}, run with `cargo test --features testing -- --ignored --test-threads=1`"
+            );
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn host_tracks_registered_activities() {
+        init();
+        let host = FakeHost::new();
+        let base_module = host.base_module::<()>(MODULE);
+        base_module
+            .register_activity(activity(&base_module, "first"))
+            .unwrap();
+        base_module
+            .register_activity(activity(&base_module, "second"))
+            .unwrap();
+        host.pump();
+        host.assert_registered("first");
+        host.assert_registered("second");
+        assert_eq!(host.take_commands().len(), 2);
+
+        base_module.unregister_activity("first");
+        host.pump();
+        host.assert_unregistered("first");
+        let names: Vec<_> = host
+            .registered_activities()
+            .iter()
+            .map(|id| id.activity().to_string())
+            .collect();
+        assert_eq!(names, ["second"]);
+        assert_eq!(host.take_commands().len(), 1);
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn property_updates_reach_subscribers_after_pump() {
+        init();
+        let host = FakeHost::new();
+        let base_module = host.base_module::<()>(MODULE);
+        let mut activity = activity(&base_module, "activity");
+        let updates = Rc::new(Cell::new(0));
+        let counter = updates.clone();
+        activity
+            .subscribe_to_property("label", move |_| counter.set(counter.get() + 1))
+            .unwrap();
+        let property = activity.get_property_any("label").unwrap();
+        let widget = activity.get_activity_widget();
+        base_module.register_activity(activity).unwrap();
+        assert_property(&base_module, "activity", "label", String::from("initial"));
+
+        property
+            .blocking_lock()
+            .set(String::from("updated"))
+            .unwrap();
+        assert_property(&base_module, "activity", "label", String::from("updated"));
+        // the subscribers are called from the main context
+        assert_eq!(updates.get(), 0);
+        host.pump();
+        assert_eq!(updates.get(), 1);
+
+        assert_mode(&base_module, "activity", ActivityMode::Minimal);
+        widget.set_mode(ActivityMode::Compact);
+        assert_mode(&base_module, "activity", ActivityMode::Compact);
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn pump_until_waits_for_timers() {
+        init();
+        let host = FakeHost::new();
+        let base_module = host.base_module::<()>(MODULE);
+        let activity = Rc::new(Mutex::new(activity(&base_module, "transient")));
+        base_module
+            .show_transient(activity, Duration::from_millis(50), None)
+            .unwrap();
+        host.pump();
+        host.assert_registered("transient");
+
+        host.pump_until(Duration::from_secs(2), |host| {
+            !host.is_registered("transient")
+        })
+        .unwrap();
+        assert!(host
+            .pump_until(Duration::from_millis(20), |host| host
+                .is_registered("transient"))
+            .is_err());
+    }
+}
@@ -0,0 +1,413 @@
+//! Headless rendering of widgets for golden-image tests
+//!
+//! The widgets are rendered with the cairo renderer, so a GPU isn't needed.
+//! GTK still needs a display, on CI it can be provided by broadway
+//! (`gtk4-broadwayd :5 & BROADWAY_DISPLAY=:5 cargo test`) or by a virtual framebuffer (`xvfb-run cargo test`).
+//!
+//! The golden images are created (or replaced) when the `DYNISLAND_UPDATE_GOLDEN` environment variable is set.
+//! The golden images of the widgets in this crate are in `tests/golden`, their tests are ignored by default because
+//! they need a display: `cargo test --features testing -- --ignored --test-threads=1`
+//!
+//! The animations of the ActivityWidget are drawn at a fixed frame time, so the result doesn't depend on the speed of the machine
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use abi::{gdk, glib, gtk, log};
+use anyhow::{anyhow, bail, Context, Result};
+use gtk::{graphene::Rect, gsk, prelude::*};
+
+use crate::graphics::util;
+
+/// How long the main context runs to let the frame clock run the tick callbacks at a new frame time
+const FRAME_WAIT: Duration = Duration::from_millis(100);
+
+/// Set this environment variable to write the rendered images as the new golden images
+pub const UPDATE_GOLDEN_ENV_VAR: &str = "DYNISLAND_UPDATE_GOLDEN";
+
+/// How to render a widget
+#[derive(Clone, Debug)]
+pub struct SnapshotOptions {
+    pub width: i32,
+    pub height: i32,
+    /// The frame time of the render, from when the widget is shown.
+    /// The animations started before the render are drawn as they are at this time,
+    /// `Duration::ZERO` draws them at their start
+    pub time: Duration,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            width: 200,
+            height: 40,
+            time: Duration::ZERO,
+        }
+    }
+}
+
+/// How different the rendered image can be from the golden image
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// The maximum difference of a channel (0-255) for a pixel to be considered the same
+    pub channel: u8,
+    /// The maximum fraction (0.0-1.0) of pixels that can be different
+    pub pixels: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            pixels: 0.001,
+        }
+    }
+}
+
+/// Initialize GTK for headless rendering
+///
+/// Forces the cairo renderer, uses broadway if there is no other display and disables the animations.
+/// This must be called before GTK is initialized, it also initializes it
+///
+/// returns `Err` if GTK can't be initialized or if there is no display:
+/// broadway is only used if `BROADWAY_DISPLAY` is set, `gtk4-broadwayd` needs to be started before
+pub fn init_headless() -> Result<()> {
+    if !gtk::is_initialized_main_thread() {
+        env::set_var("GSK_RENDERER", "cairo");
+        if env::var_os("WAYLAND_DISPLAY").is_none() && env::var_os("DISPLAY").is_none() {
+            if env::var_os("BROADWAY_DISPLAY").is_none() {
+                bail!(
+                    "there is no display, start one with `gtk4-broadwayd :5 &` and set BROADWAY_DISPLAY=:5 or use xvfb-run"
+                );
+            }
+            env::set_var("GDK_BACKEND", "broadway");
+        }
+    }
+    super::init_gtk()?;
+    if let Some(settings) = gtk::Settings::default() {
+        settings.set_gtk_enable_animations(false);
+    }
+    Ok(())
+}
+
+/// Render a widget into a texture
+///
+/// The widget is shown in a new window of the requested size with the frame time fixed at 0,
+/// then the frame time is moved to `options.time` and the widget is rendered with the cairo renderer.
+///
+/// returns `Err` if the widget already has a parent or if it can't be shown
+pub fn render_widget(
+    widget: &impl IsA<gtk::Widget>,
+    options: &SnapshotOptions,
+) -> Result<gdk::Texture> {
+    let widget = widget.as_ref();
+    if widget.parent().is_some() {
+        bail!("the widget already has a parent");
+    }
+    let window = gtk::Window::builder()
+        .decorated(false)
+        .resizable(false)
+        .default_width(options.width)
+        .default_height(options.height)
+        .build();
+    window.set_child(Some(widget));
+    // every animation started while the widget is shown starts at 0
+    util::set_fixed_frame_time(Some(0));
+    window.present();
+
+    let res = wait_for_map(widget).and_then(|_| {
+        run_main_context(FRAME_WAIT);
+        util::set_fixed_frame_time(Some(options.time.as_micros() as i64));
+        run_main_context(FRAME_WAIT);
+        snapshot_widget(widget, options.width, options.height)
+    });
+    util::set_fixed_frame_time(None);
+
+    window.set_child(None::<&gtk::Widget>);
+    window.destroy();
+    res
+}
+
+/// Render a widget and save it as a PNG
+///
+/// view [`render_widget`] for more info
+pub fn render_widget_to_png(
+    widget: &impl IsA<gtk::Widget>,
+    options: &SnapshotOptions,
+    path: &Path,
+) -> Result<()> {
+    let texture = render_widget(widget, options)?;
+    texture
+        .save_to_png(path)
+        .with_context(|| format!("failed to save {}", path.display()))
+}
+
+/// Compare a rendered texture with the golden image at `golden_path`
+///
+/// If they are too different the texture is saved next to the golden image as `<name>.actual.png`.
+/// If `DYNISLAND_UPDATE_GOLDEN` is set, the texture is saved as the new golden image instead
+///
+/// returns `Err` with the number of different pixels if the images don't match
+pub fn compare_with_golden(
+    texture: &gdk::Texture,
+    golden_path: &Path,
+    tolerance: Tolerance,
+) -> Result<()> {
+    if env::var_os(UPDATE_GOLDEN_ENV_VAR).is_some() {
+        return texture
+            .save_to_png(golden_path)
+            .with_context(|| format!("failed to save {}", golden_path.display()));
+    }
+    let golden = gdk::Texture::from_filename(golden_path).with_context(|| {
+        format!(
+            "failed to load the golden image {}, set {UPDATE_GOLDEN_ENV_VAR} to create it",
+            golden_path.display()
+        )
+    })?;
+    let res = compare_textures(texture, &golden, tolerance);
+    if res.is_err() {
+        let actual_path = actual_path(golden_path);
+        if let Err(err) = texture.save_to_png(&actual_path) {
+            log::warn!("failed to save {}: {err}", actual_path.display());
+        }
+    }
+    res.with_context(|| format!("{} doesn't match", golden_path.display()))
+}
+
+/// Render a widget and panic if it doesn't match the golden image
+///
+/// view [`render_widget`] and [`compare_with_golden`] for more info
+#[track_caller]
+pub fn assert_matches_golden(
+    widget: &impl IsA<gtk::Widget>,
+    options: &SnapshotOptions,
+    golden_path: impl AsRef<Path>,
+    tolerance: Tolerance,
+) {
+    let res = render_widget(widget, options)
+        .and_then(|texture| compare_with_golden(&texture, golden_path.as_ref(), tolerance));
+    if let Err(err) = res {
+        panic!("{err:#}");
+    }
+}
+
+fn compare_textures(
+    actual: &gdk::Texture,
+    golden: &gdk::Texture,
+    tolerance: Tolerance,
+) -> Result<()> {
+    if actual.width() != golden.width() || actual.height() != golden.height() {
+        bail!(
+            "the size is {}x{}, expected {}x{}",
+            actual.width(),
+            actual.height(),
+            golden.width(),
+            golden.height()
+        );
+    }
+    let actual = download(actual);
+    let golden = download(golden);
+    let different = actual
+        .chunks_exact(4)
+        .zip(golden.chunks_exact(4))
+        .filter(|(a, g)| {
+            a.iter()
+                .zip(g.iter())
+                .any(|(a, g)| a.abs_diff(*g) > tolerance.channel)
+        })
+        .count();
+    let total = actual.len() / 4;
+    if different as f64 > total as f64 * tolerance.pixels {
+        bail!("{different} of {total} pixels are different");
+    }
+    Ok(())
+}
+
+fn download(texture: &gdk::Texture) -> Vec<u8> {
+    let stride = texture.width() as usize * 4;
+    let mut data = vec![0; stride * texture.height() as usize];
+    texture.download(&mut data, stride);
+    data
+}
+
+fn actual_path(golden_path: &Path) -> PathBuf {
+    let stem = golden_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    golden_path.with_file_name(format!("{stem}.actual.png"))
+}
+
+fn wait_for_map(widget: &gtk::Widget) -> Result<()> {
+    let context = glib::MainContext::default();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !widget.is_mapped() {
+        if Instant::now() >= deadline {
+            return Err(anyhow!("the widget wasn't mapped, is there a display?"));
+        }
+        if !context.iteration(false) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    Ok(())
+}
+
+fn run_main_context(duration: Duration) {
+    let context = glib::MainContext::default();
+    let deadline = Instant::now() + duration;
+    loop {
+        while context.pending() {
+            context.iteration(false);
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn snapshot_widget(widget: &gtk::Widget, width: i32, height: i32) -> Result<gdk::Texture> {
+    let paintable = gtk::WidgetPaintable::new(Some(widget));
+    let snapshot = gtk::Snapshot::new();
+    paintable.snapshot(&snapshot, width as f64, height as f64);
+    let node = snapshot
+        .to_node()
+        .ok_or_else(|| anyhow!("the widget didn't draw anything"))?;
+
+    let renderer = gsk::CairoRenderer::new();
+    renderer
+        .realize(None)
+        .map_err(|err| anyhow!("failed to realize the cairo renderer: {err}"))?;
+    let texture = renderer.render_texture(
+        &node,
+        Some(&Rect::new(0.0, 0.0, width as f32, height as f32)),
+    );
+    renderer.unrealize();
+    Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use abi::gtk;
+    use gtk::prelude::*;
+
+    use super::{
+        assert_matches_golden, compare_textures, init_headless, render_widget, SnapshotOptions,
+        Tolerance,
+    };
+    use crate::graphics::{
+        activity_widget::{boxed_activity_mode::ActivityMode, ActivityWidget},
+        widgets::{rolling_char::RollingChar, scrolling_label::ScrollingLabel},
+    };
+
+    const NEEDS_DISPLAY: &str =
+        "needs a display, run with `cargo test --features testing -- --ignored --test-threads=1`";
+
+    fn golden(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{name}.png"))
+    }
+
+    fn init() {
+        // GTK can only be used from one thread, so the tests need `--test-threads=1`
+        if let Err(err) = init_headless() {
+            panic!("{err:#}, {NEEDS_DISPLAY}");
+        }
+    }
+
+    fn at(time: Duration) -> SnapshotOptions {
+        SnapshotOptions {
+            time,
+            ..Default::default()
+        }
+    }
+
+    /// An ActivityWidget that starts the transition from minimal to compact when it's shown
+    fn transition_widget() -> ActivityWidget {
+        let widget = ActivityWidget::new("snapshot-test");
+        widget.set_minimal_mode_widget(gtk::Label::new(Some("m")));
+        widget.set_compact_mode_widget(gtk::Label::new(Some("compact mode")));
+        widget.connect_map(|widget| widget.set_mode(ActivityMode::Compact));
+        widget
+    }
+
+    fn scrolling_label(text: &str) -> ScrollingLabel {
+        let label = ScrollingLabel::new();
+        label.set_max_width(100);
+        label.set_text(text);
+        label
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn activity_widget_transition_matches_golden() {
+        init();
+        let middle = at(Duration::from_millis(150));
+        let end = at(Duration::from_secs(5));
+        assert_matches_golden(
+            &transition_widget(),
+            &middle,
+            golden("activity_widget_transition_middle"),
+            Tolerance::default(),
+        );
+        assert_matches_golden(
+            &transition_widget(),
+            &end,
+            golden("activity_widget_transition_end"),
+            Tolerance::default(),
+        );
+
+        // the frame time decides the frame
+        let middle = render_widget(&transition_widget(), &middle).unwrap();
+        let end = render_widget(&transition_widget(), &end).unwrap();
+        assert!(compare_textures(&end, &middle, Tolerance::default()).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn scrolling_label_matches_golden() {
+        init();
+        assert_matches_golden(
+            &scrolling_label("short"),
+            &SnapshotOptions::default(),
+            golden("scrolling_label_short"),
+            Tolerance::default(),
+        );
+        // the text is wider than the max width, it's faded on the sides and scrolled
+        assert_matches_golden(
+            &scrolling_label("a text that is too long for the label"),
+            &at(Duration::from_secs(1)),
+            golden("scrolling_label_scrolling"),
+            Tolerance::default(),
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a display, run with `--ignored --test-threads=1`"]
+    fn rolling_char_matches_golden() {
+        init();
+        assert_matches_golden(
+            &RollingChar::new(Some('4')),
+            &SnapshotOptions::default(),
+            golden("rolling_char"),
+            Tolerance::default(),
+        );
+        // the char changes when it's shown, it's drawn after it rolled
+        let rolling = RollingChar::new(Some('4'));
+        rolling.connect_map(|rolling| rolling.set_current_char('7'));
+        assert_matches_golden(
+            &rolling,
+            &at(Duration::from_secs(5)),
+            golden("rolling_char_changed"),
+            Tolerance::default(),
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use abi::{glib, module::ActivityIdentifier};
+
+pub(crate) struct TransientState {
+    pub(crate) source: glib::SourceId,
+    pub(crate) cleanup: Option<Box<dyn FnOnce()>>,
+    /// How long the activity is shown after the last update
+    pub(crate) duration: Duration,
+    /// When the activity should be unregistered, the timer is started again if it fires earlier
+    pub(crate) deadline: Instant,
+}
+
+impl TransientState {
+    /// Stop the timer and run the cleanup callback
+    pub(crate) fn cancel(self) {
+        self.source.remove();
+        if let Some(cleanup) = self.cleanup {
+            cleanup();
+        }
+    }
+}
+
+/// Keeps the timers of the transient activities of a module
+#[derive(Default)]
+pub(crate) struct TransientActivities {
+    map: RefCell<HashMap<ActivityIdentifier, TransientState>>,
+}
+
+impl TransientActivities {
+    pub(crate) fn insert(&self, activity_id: ActivityIdentifier, state: TransientState) {
+        self.map.borrow_mut().insert(activity_id, state);
+    }
+
+    pub(crate) fn take(&self, activity_id: &ActivityIdentifier) -> Option<TransientState> {
+        self.map.borrow_mut().remove(activity_id)
+    }
+
+    pub(crate) fn contains(&self, activity_id: &ActivityIdentifier) -> bool {
+        self.map.borrow().contains_key(activity_id)
+    }
+
+    /// Move the deadline of a shown activity to its duration from now
+    ///
+    /// does nothing if the activity isn't shown as transient
+    pub(crate) fn extend(&self, activity_id: &ActivityIdentifier) {
+        if let Some(state) = self.map.borrow_mut().get_mut(activity_id) {
+            state.deadline = Instant::now() + state.duration;
+        }
+    }
+}